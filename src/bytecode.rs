@@ -0,0 +1,421 @@
+use crate::parser::ast::MacroArg;
+use crate::parser::types::Span;
+use crate::scheduling::Step;
+use crate::source_map::step_spans;
+use crate::transformer::analysis::Symbols;
+use crate::transformer::ir_gen::{IRBlock, ValueSource, LABEL_BYTES};
+use crate::transformer::std_evm::opcode;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+
+const POP: u8 = 0x50;
+const JUMPDEST: u8 = 0x5b;
+const PUSH0: u8 = 0x5f;
+const DUP1: u8 = 0x80;
+const SWAP1: u8 = 0x90;
+/// Bytes pushed for a value that's only known at link time.
+const PLACEHOLDER_BYTES: usize = 32;
+
+/// Values known ahead of assembling by name: the value of a Huff constant or macro argument, or
+/// the code of an invoked macro.
+pub type Links = BTreeMap<String, Vec<u8>>;
+
+/// Parses links from lines of the form `NAME = 0x<hex>`, ignoring empty lines and `//` comments.
+pub fn parse_links(src: &str) -> Result<Links, String> {
+    src.lines()
+        .enumerate()
+        .map(|(i, line)| (i, line.split("//").next().unwrap().trim()))
+        .filter(|(_, line)| !line.is_empty())
+        .map(|(i, line)| {
+            let (name, hex) = line
+                .split_once('=')
+                .ok_or_else(|| format!("Line {}: expected `NAME = 0x<hex>`", i + 1))?;
+            let hex = hex.trim();
+            let bytes = decode_hex(hex.strip_prefix("0x").unwrap_or(hex))
+                .ok_or_else(|| format!("Line {}: invalid hex \"{}\"", i + 1, hex))?;
+            Ok((name.trim().to_string(), bytes))
+        })
+        .collect()
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BytecodeError {
+    /// (label, macro) A label is pushed whose offset, or the offset it's pushed at, depends on the
+    /// size of an unlinked macro.
+    UnknownLabelOffset(String, String),
+    /// (label, offset) The label's offset doesn't fit the bytes a label is pushed with.
+    LabelOutOfRange(String, usize),
+    /// (name, bytes) The value linked for a constant or macro argument exceeds 32 bytes.
+    ValueTooLong(String, usize),
+    /// The name of an unlinked constant or macro argument doesn't fit its 32 byte placeholder.
+    PlaceholderTooLong(String),
+}
+
+impl fmt::Display for BytecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::UnknownLabelOffset(label, macro_name) => write!(
+                f,
+                "Offset of label {} depends on the size of unlinked macro {}",
+                label, macro_name
+            ),
+            Self::LabelOutOfRange(label, offset) => write!(
+                f,
+                "Offset of label {} ({:#x}) doesn't fit in {} bytes",
+                label, offset, LABEL_BYTES
+            ),
+            Self::ValueTooLong(name, bytes) => {
+                write!(f, "Value of {} ({} bytes) exceeds 32 bytes", name, bytes)
+            }
+            Self::PlaceholderTooLong(name) => write!(
+                f,
+                "Placeholder of {} doesn't fit in {} bytes, link its value instead",
+                name, PLACEHOLDER_BYTES
+            ),
+        }
+    }
+}
+
+impl std::error::Error for BytecodeError {}
+
+/// Assembled code of a function.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Bytecode {
+    /// Hex encoded code, possibly containing placeholders.
+    pub code: String,
+    /// Token span every instruction was generated for, an unlinked macro counting as a single
+    /// instruction.
+    pub spans: Vec<Option<Span>>,
+    /// Byte offsets in the code of every pushed label offset, to which the offset the code ends up
+    /// at has to be added when linking.
+    pub relocations: Vec<usize>,
+}
+
+#[derive(Debug, Clone)]
+enum Piece {
+    Code(Vec<u8>),
+    /// Jump destination marked by the label.
+    LabelDef(String),
+    /// Pushes the offset of the label.
+    LabelPush(String),
+    /// Value of a constant or macro argument to be filled in at link time.
+    Value(String),
+    /// Code of a macro to be filled in at link time.
+    Macro(String),
+}
+
+fn append_hex(out: &mut String, code: &[u8]) {
+    for byte in code {
+        out.push_str(&format!("{:02x}", byte));
+    }
+}
+
+/// Drops the leading zero bytes of `value`.
+fn trim_zeros(value: &[u8]) -> &[u8] {
+    &value[value.iter().take_while(|byte| **byte == 0).count()..]
+}
+
+/// Pushes `value`, which must not exceed 32 bytes once trimmed.
+fn push(value: &[u8]) -> Vec<u8> {
+    let value = trim_zeros(value);
+    assert!(value.len() <= 32, "Pushed value exceeds 32 bytes");
+    let mut code = vec![PUSH0 + value.len() as u8];
+    code.extend_from_slice(value);
+    code
+}
+
+/// Pushes `name`'s linked value, or a placeholder for it if it isn't linked.
+fn push_linked(name: &str, links: &Links) -> Result<Piece, BytecodeError> {
+    match links.get(name) {
+        Some(value) if trim_zeros(value).len() > 32 => Err(BytecodeError::ValueTooLong(
+            name.to_string(),
+            trim_zeros(value).len(),
+        )),
+        Some(value) => Ok(Piece::Code(push(value))),
+        None if placeholder(name, 0).len() > PLACEHOLDER_BYTES * 2 => {
+            Err(BytecodeError::PlaceholderTooLong(name.to_string()))
+        }
+        None => Ok(Piece::Value(name.to_string())),
+    }
+}
+
+/// Size of the instruction starting with `opcode`, including the data it pushes.
+fn instruction_size(opcode: u8) -> usize {
+    if (PUSH0..=PUSH0 + 32).contains(&opcode) {
+        1 + (opcode - PUSH0) as usize
+    } else {
+        1
+    }
+}
+
+/// Amount of instructions in `code`, the data of a `PUSH` not being counted.
+fn instruction_count(code: &[u8]) -> usize {
+    let mut count = 0;
+    let mut i = 0;
+    while i < code.len() {
+        i += instruction_size(code[i]);
+        count += 1;
+    }
+    count
+}
+
+/// Placeholder for `name` spanning `width` hex characters (at least the name).
+fn placeholder(name: &str, width: usize) -> String {
+    format!("{:_<width$}", format!("__{}__", name), width = width)
+}
+
+/// Assembles the scheduled blocks of a function (`block_steps[i]` being the steps of `blocks[i]`)
+/// into hex encoded EVM bytecode. Label offsets are relative to the start of the function's code,
+/// the offsets they're pushed at being returned as relocations for them to be moved with it. Values and macros that aren't in `links` are left as placeholders of the form `__NAME__` to be
+/// replaced at link time, constants and macro arguments being pushed with a `PUSH32` whose
+/// placeholder is padded to 32 bytes.
+pub fn assemble(
+    symbols: &Symbols,
+    blocks: &[IRBlock],
+    block_steps: Vec<Vec<Step>>,
+    links: &Links,
+) -> Result<Bytecode, BytecodeError> {
+    let mut pieces = vec![];
+    let mut spans = vec![];
+    for (block, steps) in blocks.iter().zip(block_steps) {
+        if let Some(label) = &block.label {
+            pieces.push(Piece::LabelDef(label.clone()));
+            spans.push(None);
+        }
+        let step_spans = step_spans(block, &steps);
+        for (step, span) in steps.into_iter().zip(step_spans) {
+            let piece = match step {
+                Step::Dup(depth) => Piece::Code(vec![DUP1 + depth as u8 - 1]),
+                Step::Swap(depth) => Piece::Code(vec![SWAP1 + depth as u8 - 1]),
+                Step::Pop => Piece::Code(vec![POP]),
+                Step::Comp(id, as_variant) => match &block.sources[id] {
+                    source @ ValueSource::Op(_) => {
+                        let ident = source.huff_repr(symbols, as_variant);
+                        let byte = opcode(&ident)
+                            .unwrap_or_else(|| panic!("No opcode for operation {}", ident));
+                        Piece::Code(vec![byte])
+                    }
+                    ValueSource::MacroInvoke(ident, _) => match links.get(ident) {
+                        Some(code) => Piece::Code(code.clone()),
+                        None => Piece::Macro(ident.clone()),
+                    },
+                    ValueSource::MacroArg(MacroArg::Num(num)) => {
+                        Piece::Code(push(&num.to_bytes_be()))
+                    }
+                    ValueSource::MacroArg(MacroArg::ArgRef(ident))
+                    | ValueSource::HuffConst(ident) => push_linked(ident, links)?,
+                    ValueSource::Label(label) => Piece::LabelPush(label.clone()),
+                    ValueSource::TopLevelInput(_) => {
+                        panic!("Invalid instruction sequence, top-level-input cannot be comp")
+                    }
+                    ValueSource::Projection(_, _) => {
+                        panic!("Invalid instruction sequence, projection cannot be comp")
+                    }
+                },
+            };
+            let instructions = match &piece {
+                Piece::Code(code) => instruction_count(code),
+                _ => 1,
+            };
+            spans.extend(std::iter::repeat_n(span, instructions));
+            pieces.push(piece);
+        }
+    }
+
+    // Offset of every piece and label, or the unlinked macro before it.
+    let mut offsets: Vec<Result<usize, &str>> = Vec::with_capacity(pieces.len());
+    let mut labels: HashMap<&str, Result<usize, &str>> = HashMap::new();
+    let mut offset = Ok(0);
+    for piece in pieces.iter() {
+        if let Piece::LabelDef(label) = piece {
+            labels.insert(label, offset);
+        }
+        offsets.push(offset);
+        offset = offset.and_then(|offset| {
+            Ok(offset
+                + match piece {
+                    Piece::Code(code) => code.len(),
+                    Piece::LabelDef(_) => 1,
+                    Piece::LabelPush(_) => 1 + LABEL_BYTES as usize,
+                    Piece::Value(_) => 1 + PLACEHOLDER_BYTES,
+                    Piece::Macro(ident) => return Err(ident.as_str()),
+                })
+        });
+    }
+
+    let mut out = String::new();
+    let mut relocations = vec![];
+    for (piece, offset) in pieces.iter().zip(offsets) {
+        match piece {
+            Piece::Code(code) => append_hex(&mut out, code),
+            Piece::LabelDef(_) => append_hex(&mut out, &[JUMPDEST]),
+            Piece::LabelPush(label) => {
+                let unknown_offset = |macro_name: &str| {
+                    BytecodeError::UnknownLabelOffset(label.clone(), macro_name.to_string())
+                };
+                relocations.push(offset.map_err(unknown_offset)? + 1);
+                let offset = labels[label.as_str()].map_err(unknown_offset)?;
+                let offset_bytes = (offset as u64).to_be_bytes();
+                let (high, low) = offset_bytes.split_at(8 - LABEL_BYTES as usize);
+                if high.iter().any(|byte| *byte != 0) {
+                    return Err(BytecodeError::LabelOutOfRange(label.clone(), offset));
+                }
+                append_hex(&mut out, &[PUSH0 + LABEL_BYTES as u8]);
+                append_hex(&mut out, low);
+            }
+            Piece::Value(ident) => {
+                append_hex(&mut out, &[PUSH0 + PLACEHOLDER_BYTES as u8]);
+                out.push_str(&placeholder(ident, PLACEHOLDER_BYTES * 2));
+            }
+            Piece::Macro(ident) => out.push_str(&placeholder(ident, 0)),
+        }
+    }
+    Ok(Bytecode {
+        code: out,
+        spans,
+        relocations,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parser::types::resolve_span_span;
+    use crate::parser::{lexer, parser};
+    use crate::scheduling::astar::{AStarScheduler, SearchBudget};
+    use crate::scheduling::cost::SwapCount;
+    use crate::scheduling::schedulers::Dijkstra;
+    use crate::source_map::solidity_source_map;
+    use crate::transformer::analysis::{validate_and_get_symbols, Symbol};
+    use crate::transformer::ir_gen::gen_ir;
+
+    fn assemble_src(src: &str, func: &str, links: &Links) -> Result<Bytecode, BytecodeError> {
+        let tokens = lexer::lex(src).0.unwrap();
+        let tokens = tokens.into_iter().map(|t| t.inner).collect();
+        let symbols = validate_and_get_symbols(parser::parse_tokens(tokens).0.unwrap()).unwrap();
+        let Symbol::Function(func) = &symbols[func].inner else {
+            panic!("{} is not a function", func);
+        };
+        let blocks = gen_ir(func, &symbols, false).unwrap();
+        let block_steps = blocks
+            .iter()
+            .map(|block| {
+                Dijkstra
+                    .schedule(&block.graph, &SwapCount, 1024, SearchBudget::default())
+                    .unwrap()
+                    .0
+            })
+            .collect();
+        assemble(&symbols, &blocks, block_steps, links)
+    }
+
+    #[test]
+    fn test_minimal_push() {
+        assert_eq!(push(&[]), vec![PUSH0]);
+        assert_eq!(push(&[0, 0]), vec![PUSH0]);
+        assert_eq!(push(&[0, 1, 2]), vec![0x61, 1, 2]);
+        assert_eq!(push(&[0xff; 32]), [&[0x7f][..], &[0xff; 32]].concat());
+    }
+
+    #[test]
+    fn test_parse_links() {
+        let links = parse_links("// comment\nA = 0x01ff\n\nB=00 // trailing\n").unwrap();
+        assert_eq!(links["A"], vec![0x01, 0xff]);
+        assert_eq!(links["B"], vec![0x00]);
+        assert!(parse_links("A = 0x1").is_err());
+        assert!(parse_links("A").is_err());
+    }
+
+    #[test]
+    fn test_labels_and_links() {
+        let src = include_str!("../examples/branching.balls");
+        let bytecode = assemble_src(src, "CLAMPED_SUB", &Links::new()).unwrap();
+        let bytes = decode_hex(&bytecode.code).unwrap();
+        assert_eq!(bytecode.spans.len(), instruction_count(&bytes));
+        // Every pushed label points at a jump destination.
+        let mut i = 0;
+        while i < bytes.len() {
+            if bytes[i] == PUSH0 + LABEL_BYTES as u8 {
+                let target = u16::from_be_bytes([bytes[i + 1], bytes[i + 2]]) as usize;
+                assert_eq!(bytes[target], JUMPDEST);
+            }
+            i += instruction_size(bytes[i]);
+        }
+        assert!(!bytecode.relocations.is_empty());
+        for &relocation in bytecode.relocations.iter() {
+            assert_eq!(bytes[relocation - 1], PUSH0 + LABEL_BYTES as u8);
+        }
+
+        let src = "extern CHECK() stack(0, 0) reads(CONTROL_FLOW)\nfn F() -> () {\n    CHECK()\n    if iszero(caller()) {\n        stop()\n    }\n}\n";
+        assert_eq!(
+            assemble_src(src, "F", &Links::new()),
+            Err(BytecodeError::UnknownLabelOffset(
+                "if_0_end".into(),
+                "CHECK".into()
+            ))
+        );
+        let links = Links::from([("CHECK".to_string(), vec![0x5b])]);
+        assert!(assemble_src(src, "F", &links).is_ok());
+    }
+
+    #[test]
+    fn test_linked_value_sizes() {
+        let src = "const C\nfn F() -> () {\n    sstore(0x1, C)\n}\n";
+        let links = Links::from([("C".to_string(), [&[0; 8][..], &[0xff; 32]].concat())]);
+        let bytecode = assemble_src(src, "F", &links).unwrap();
+        assert!(bytecode
+            .code
+            .contains(&format!("{:02x}{}", PUSH0 + 32, "ff".repeat(32))));
+        let links = Links::from([("C".to_string(), vec![0x01; 33])]);
+        assert_eq!(
+            assemble_src(src, "F", &links),
+            Err(BytecodeError::ValueTooLong("C".into(), 33))
+        );
+
+        let name = "C".repeat(PLACEHOLDER_BYTES * 2 - 4);
+        let bytecode = assemble_src(&src.replace('C', &name), "F", &Links::new()).unwrap();
+        assert!(bytecode.code.contains(&format!("__{}__", name)));
+        assert_eq!(bytecode.code.len(), 2 * (1 + PLACEHOLDER_BYTES + 2 + 1));
+        let name = "C".repeat(PLACEHOLDER_BYTES * 2 - 3);
+        assert_eq!(
+            assemble_src(&src.replace('C', &name), "F", &Links::new()),
+            Err(BytecodeError::PlaceholderTooLong(name))
+        );
+    }
+
+    #[test]
+    fn test_source_map() {
+        let src = "fn F() -> () {\n    sstore(0x1, caller())\n}\n";
+        let bytecode = assemble_src(src, "F", &Links::new()).unwrap();
+        let tokens = lexer::lex(src).0.unwrap();
+        let located: Vec<_> = bytecode
+            .spans
+            .iter()
+            .map(|span| {
+                let span = span.as_ref().unwrap();
+                let span = resolve_span_span(span, &tokens);
+                &src[span]
+            })
+            .collect();
+        assert_eq!(
+            bytecode.code,
+            format!("{:02x}{:02x}01{:02x}", 0x33, PUSH0 + 1, 0x55)
+        );
+        assert_eq!(located, ["caller()", "0x1", "sstore(0x1, caller())"]);
+
+        assert_eq!(
+            solidity_source_map(&[Some(0..4), Some(0..4), Some(5..9), None, Some(5..7)]),
+            "0:4:0;;5;-1:-1:-1;5:2:0"
+        );
+    }
+}
@@ -0,0 +1,163 @@
+/// Line of a diff, tagged with how it changed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Line<'a> {
+    Same(&'a str),
+    Removed(&'a str),
+    Added(&'a str),
+}
+
+/// Shortest edit turning `old` into `new`, from the longest common subsequence of their lines.
+fn diff_lines<'a>(old: &[&'a str], new: &[&'a str]) -> Vec<Line<'a>> {
+    // Only the part between the common prefix and suffix has to be searched.
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let old_mid = &old[prefix..old.len() - suffix];
+    let new_mid = &new[prefix..new.len() - suffix];
+
+    // lcs[i][j]: length of the longest common subsequence of old_mid[i..] and new_mid[j..].
+    let mut lcs = vec![vec![0u32; new_mid.len() + 1]; old_mid.len() + 1];
+    for i in (0..old_mid.len()).rev() {
+        for j in (0..new_mid.len()).rev() {
+            lcs[i][j] = if old_mid[i] == new_mid[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let mut lines: Vec<_> = old[..prefix].iter().map(|line| Line::Same(line)).collect();
+    let (mut i, mut j) = (0, 0);
+    while i < old_mid.len() || j < new_mid.len() {
+        if i < old_mid.len() && j < new_mid.len() && old_mid[i] == new_mid[j] {
+            lines.push(Line::Same(old_mid[i]));
+            i += 1;
+            j += 1;
+        } else if j == new_mid.len() || (i < old_mid.len() && lcs[i + 1][j] >= lcs[i][j + 1]) {
+            lines.push(Line::Removed(old_mid[i]));
+            i += 1;
+        } else {
+            lines.push(Line::Added(new_mid[j]));
+            j += 1;
+        }
+    }
+    lines.extend(
+        old[old.len() - suffix..]
+            .iter()
+            .map(|line| Line::Same(line)),
+    );
+    lines
+}
+
+/// Line based unified diff turning `old` into `new` with `context` unchanged lines around every
+/// change, empty if they're the same. Like with `git diff` every hunk header ends with the closest
+/// line before the hunk that starts a Huff macro.
+pub fn unified_diff(
+    old: &str,
+    new: &str,
+    old_name: &str,
+    new_name: &str,
+    context: usize,
+) -> String {
+    let old_lines: Vec<_> = old.lines().collect();
+    let new_lines: Vec<_> = new.lines().collect();
+    let lines = diff_lines(&old_lines, &new_lines);
+
+    let changed: Vec<usize> = (0..lines.len())
+        .filter(|i| !matches!(lines[*i], Line::Same(_)))
+        .collect();
+    if changed.is_empty() {
+        return String::new();
+    }
+
+    // Ranges of lines making up a hunk, changes separated by few enough lines share one.
+    let mut hunks: Vec<(usize, usize)> = vec![];
+    for i in changed {
+        let start = i.saturating_sub(context);
+        let end = (i + context + 1).min(lines.len());
+        match hunks.last_mut() {
+            Some((_, last_end)) if start <= *last_end => *last_end = end,
+            _ => hunks.push((start, end)),
+        }
+    }
+
+    let mut out = format!("--- {}\n+++ {}\n", old_name, new_name);
+    // Line numbers (0-based) in the old and new text at the start of the current position.
+    let (mut old_line, mut new_line) = (0, 0);
+    let mut position = 0;
+    for (start, end) in hunks {
+        for line in &lines[position..start] {
+            match line {
+                Line::Same(_) => {
+                    old_line += 1;
+                    new_line += 1;
+                }
+                Line::Removed(_) => old_line += 1,
+                Line::Added(_) => new_line += 1,
+            }
+        }
+        let hunk = &lines[start..end];
+        let old_len = hunk
+            .iter()
+            .filter(|line| !matches!(line, Line::Added(_)))
+            .count();
+        let new_len = hunk
+            .iter()
+            .filter(|line| !matches!(line, Line::Removed(_)))
+            .count();
+        let section = old_lines[..old_line]
+            .iter()
+            .rev()
+            .find(|line| line.trim_start().starts_with("#define macro"))
+            .map_or(String::new(), |line| format!(" {}", line.trim()));
+        // Empty ranges are numbered by the line before them.
+        out.push_str(&format!(
+            "@@ -{},{} +{},{} @@{}\n",
+            old_line + (old_len > 0) as usize,
+            old_len,
+            new_line + (new_len > 0) as usize,
+            new_len,
+            section
+        ));
+        for line in hunk {
+            match line {
+                Line::Same(text) => {
+                    out.push_str(&format!(" {}\n", text));
+                    old_line += 1;
+                    new_line += 1;
+                }
+                Line::Removed(text) => {
+                    out.push_str(&format!("-{}\n", text));
+                    old_line += 1;
+                }
+                Line::Added(text) => {
+                    out.push_str(&format!("+{}\n", text));
+                    new_line += 1;
+                }
+            }
+        }
+        position = end;
+    }
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_unified_diff() {
+        let old = "#define macro A() = {\n    a\n    b\n    c\n}\nx\ny\nz\nw\nv\n";
+        let new = "#define macro A() = {\n    a\n    B\n    c\n}\nx\ny\nz\nw\nv\nu\n";
+        assert_eq!(unified_diff(old, old, "a", "b", 3), "");
+        assert_eq!(
+            unified_diff(old, new, "a", "b", 1),
+            "--- a\n+++ b\n@@ -2,3 +2,3 @@ #define macro A() = {\n     a\n-    b\n+    B\n     c\n@@ -10,1 +10,2 @@ #define macro A() = {\n v\n+u\n"
+        );
+    }
+}
@@ -1,8 +1,10 @@
 use crate::parser::ast::{Function, MacroArg};
-use crate::scheduling::ir::{CompNodeId, IRGraph};
+use crate::parser::types::Span;
+use crate::scheduling::ir::CompNodeId;
 use crate::scheduling::Step;
+use crate::source_map::step_spans;
 use crate::transformer::analysis::Symbols;
-use crate::transformer::ir_gen::ValueSource;
+use crate::transformer::ir_gen::{IRBlock, ValueSource};
 
 /// Minimum character width for the comment start such that at least the ending "// returns: [..."
 /// is nicely formatted.
@@ -20,17 +22,49 @@ pub fn validate_format_params(comment_start: usize, indent: usize) -> Option<Str
     }
 }
 
+fn with_stack_comment(
+    lone_line: String,
+    stack: &[String],
+    location: Option<String>,
+    comment_start: usize,
+    main_width: usize,
+    indent: &str,
+) -> String {
+    let mut stack_repr = if stack.len() > 17 {
+        format!("[..., {}]", stack[stack.len() - 17..].join(", "))
+    } else {
+        format!("[{}]", stack.join(", "))
+    };
+    if let Some(location) = location {
+        stack_repr.push_str(" @ ");
+        stack_repr.push_str(&location);
+    }
+    // +1 accounts for the space between the op representation and the stack comment.
+    if lone_line.len() + 1 >= comment_start {
+        format!(
+            "{}\n{indent}//{}{}",
+            lone_line,
+            " ".repeat(main_width + 1),
+            stack_repr
+        )
+    } else {
+        format!("{:<comment_start$}// {}", lone_line, stack_repr)
+    }
+}
+
 // Huff macro arguments can be: opcodes, constants, macro_args
 
+/// Formats the scheduled blocks of a function (`block_steps[i]` being the steps of `blocks[i]`) as
+/// a Huff macro. If given, `locate` turns the token span every instruction was generated for into
+/// a location that's appended to its stack comment (e.g. `// [a, b] @ 3:12`).
 pub fn format_with_stack_comments(
     func: &Function,
     symbols: &Symbols,
-    graph: &IRGraph,
-    sources: &[ValueSource],
-    assignments: &[(String, CompNodeId)],
-    steps: Vec<Step>,
+    blocks: &[IRBlock],
+    block_steps: Vec<Vec<Step>>,
     comment_start: usize,
     indent: usize,
+    locate: Option<&dyn Fn(&Span) -> String>,
 ) -> String {
     let mut out = format!(
         "#define macro {}({}) = takes({}) returns({}) {{\n",
@@ -63,83 +97,107 @@ pub fn format_with_stack_comments(
     out.push_str(&line);
     out.push('\n');
 
-    for step in steps {
-        let op_repr = match step {
-            Step::Comp(id, as_variant) => sources[id].huff_repr(symbols, as_variant),
-            Step::Dup(depth) => format!("dup{}", depth),
-            Step::Swap(depth) => format!("swap{}", depth),
-            Step::Pop => "pop".into(),
-        };
-        // TODO: Generalize
-        let op_repr = if op_repr == "diff" { "sub" } else { &op_repr };
-        match step {
-            Step::Comp(id, _) => {
-                let mut args = vec![];
-                let node = &graph.nodes[id];
-                for _ in 0..node.operands.len() {
-                    args.push(stack.pop().expect("Invalid instruction sequence"));
-                }
-                let assignment = assignments
-                    .iter()
-                    .find(|(_, statement_id)| *statement_id == id);
-                if node.produces_value {
-                    let value_repr = match assignment {
-                        Some((ident, _)) => ident.clone(),
-                        None => match &sources[id] {
-                            ValueSource::MacroInvoke(ident, macro_args) => {
-                                format!(
-                                    "{}<{}>({})",
-                                    ident,
-                                    macro_args
-                                        .iter()
-                                        .map(MacroArg::balls_repr)
-                                        .collect::<Vec<String>>()
-                                        .join(", "),
-                                    args.join(", ")
-                                )
-                            }
-                            ValueSource::Op(_) => format!("{}({})", op_repr, args.join(", ")),
-                            ValueSource::MacroArg(arg) => arg.balls_repr(),
-                            ValueSource::HuffConst(ident) => ident.clone(),
-                            ValueSource::TopLevelInput(_) => panic!(
-                                "Invalid instruction sequence, top-level-input cannot be comp"
-                            ),
-                        },
+    for (block, steps) in blocks.iter().zip(block_steps) {
+        let IRBlock {
+            label,
+            inputs,
+            graph,
+            sources,
+            assignments,
+        } = block;
+
+        stack = inputs.iter().rev().cloned().collect();
+        if let Some(label) = label {
+            let line = with_stack_comment(
+                format!("{indent}{}:", label),
+                &stack,
+                None,
+                comment_start,
+                main_width,
+                &indent,
+            );
+            out.push_str(&line);
+            out.push('\n');
+        }
+
+        let spans = step_spans(block, &steps);
+        for (step, span) in steps.into_iter().zip(spans) {
+            let op_repr = match step {
+                Step::Comp(id, as_variant) => sources[id].huff_repr(symbols, as_variant),
+                Step::Dup(depth) => format!("dup{}", depth),
+                Step::Swap(depth) => format!("swap{}", depth),
+                Step::Pop => "pop".into(),
+            };
+            match step {
+                Step::Comp(id, _) => {
+                    let mut args = vec![];
+                    let node = &graph.nodes[id];
+                    for _ in 0..node.operands.len() {
+                        args.push(stack.pop().expect("Invalid instruction sequence"));
+                    }
+                    let assignment_of = |id: CompNodeId| {
+                        assignments
+                            .iter()
+                            .find(|(_, statement_id)| *statement_id == id)
+                            .map(|(ident, _)| ident.clone())
+                    };
+                    let call_repr = || match &sources[id] {
+                        ValueSource::MacroInvoke(ident, macro_args) => {
+                            format!(
+                                "{}<{}>({})",
+                                ident,
+                                macro_args
+                                    .iter()
+                                    .map(MacroArg::balls_repr)
+                                    .collect::<Vec<String>>()
+                                    .join(", "),
+                                args.join(", ")
+                            )
+                        }
+                        ValueSource::Op(_) => format!("{}({})", op_repr, args.join(", ")),
+                        ValueSource::MacroArg(arg) => arg.balls_repr(),
+                        ValueSource::HuffConst(ident) | ValueSource::Label(ident) => ident.clone(),
+                        ValueSource::TopLevelInput(_) => {
+                            panic!("Invalid instruction sequence, top-level-input cannot be comp")
+                        }
+                        ValueSource::Projection(_, _) => {
+                            panic!("Invalid instruction sequence, projection cannot be comp")
+                        }
                     };
-                    stack.push(value_repr);
+                    if node.produces_value {
+                        stack.push(assignment_of(id).unwrap_or_else(call_repr));
+                    }
+                    // Outputs of multi-output nodes are pushed deepest first.
+                    for (i, projection_id) in node.projections.iter().enumerate().rev() {
+                        stack.push(
+                            assignment_of(*projection_id)
+                                .unwrap_or_else(|| format!("{}[{}]", call_repr(), i)),
+                        );
+                    }
+                }
+                Step::Dup(depth) => {
+                    stack.push(stack[stack.len() - depth].clone());
+                }
+                Step::Swap(depth) => {
+                    let last_idx = stack.len() - 1;
+                    stack.swap(last_idx, last_idx - depth);
+                }
+                Step::Pop => {
+                    stack.pop();
                 }
             }
-            Step::Dup(depth) => {
-                stack.push(stack[stack.len() - depth].clone());
-            }
-            Step::Swap(depth) => {
-                let last_idx = stack.len() - 1;
-                stack.swap(last_idx, last_idx - depth);
-            }
-            Step::Pop => {
-                stack.pop();
-            }
-        }
 
-        let lone_line = format!("{indent}{}", op_repr);
-        let stack_repr = if stack.len() > 17 {
-            format!("[..., {}]", stack[stack.len() - 17..].join(", "))
-        } else {
-            format!("[{}]", stack.join(", "))
-        };
-        // +1 accounts for the space between the op representation and the stack comment.
-        let line = if lone_line.len() + 1 >= comment_start {
-            format!(
-                "{}\n{indent}//{}{}",
-                lone_line,
-                " ".repeat(main_width + 1),
-                stack_repr
-            )
-        } else {
-            format!("{:<comment_start$}// {}", lone_line, stack_repr)
-        };
-        out.push_str(&line);
-        out.push('\n');
+            let line = with_stack_comment(
+                format!("{indent}{}", op_repr),
+                &stack,
+                locate.zip(span).map(|(locate, span)| locate(&span)),
+                comment_start,
+                main_width,
+                &indent,
+            );
+            out.push_str(&line);
+            out.push('\n');
+        }
     }
 
     let line = format!(
@@ -1,6 +1,12 @@
+pub mod bytecode;
+pub mod diff;
 pub mod huff_formatter;
 pub mod parser;
 pub mod scheduling;
+pub mod source_map;
+pub mod splice;
+#[cfg(test)]
+mod test_utils;
 pub mod transformer;
 pub mod utils;
 
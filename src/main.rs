@@ -1,15 +1,113 @@
+use balls::bytecode::{assemble, parse_links, Links};
+use balls::diff::unified_diff;
 use balls::huff_formatter;
-use balls::parser::{error_printing::print_errors, lexer, parser, types::resolve_span_span};
-use balls::scheduling::astar::AStarScheduler;
-use balls::scheduling::schedulers::{Dijkstra, Guessooor};
+use balls::parser::{
+    error_printing::{print_errors, print_schedule_error, print_semantic_errors},
+    lexer, parser,
+    types::resolve_span_span,
+};
+use balls::scheduling::astar::{AStarScheduler, SchedulingTracker, SearchBudget};
+use balls::scheduling::auto_guess::{tune_guess, Schedules, TunedGuess, DEFAULT_GUESSOR_FACTOR};
+use balls::scheduling::beam::BeamScheduler;
+use balls::scheduling::cost::{BytecodeSize, CostModel, GasCost, SwapCount, Weighted};
+use balls::scheduling::explored::SearchArena;
+use balls::scheduling::ida::IterativeDeepening;
+use balls::scheduling::ir::IRGraph;
+use balls::scheduling::parallel::HashDistributed;
+use balls::scheduling::schedulers::{Dijkstra, Guessooor, LowerBound};
+use balls::scheduling::window::reoptimize_windows;
+use balls::scheduling::{ScheduleError, Step};
+use balls::source_map::{byte_span, line_col, solidity_source_map};
+use balls::splice::splice_macros;
 use balls::transformer::analysis::{validate_and_get_symbols, Symbol, Symbols};
-use balls::transformer::ir_gen::gen_ir;
+use balls::transformer::cse::eliminate_common_subexpressions;
+use balls::transformer::huff_import::resolve_huff_imports;
+use balls::transformer::ir_gen::{gen_ir, IRBlock, ValueSource};
+use balls::transformer::remat::mark_rematerializable;
 use balls::TimeDelta;
 use clap::Parser;
-use std::time::Instant;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+#[derive(Clone, Copy, Debug)]
+enum Objective {
+    Swaps,
+    Gas,
+    Size,
+    /// Weights of gas and size respectively.
+    Weighted(u32, u32),
+}
+
+impl FromStr for Objective {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "swaps" => Ok(Self::Swaps),
+            "gas" => Ok(Self::Gas),
+            "size" => Ok(Self::Size),
+            _ => {
+                let weights = s
+                    .strip_prefix("weighted=")
+                    .and_then(|weights| weights.split_once(','))
+                    .and_then(|(gas, size)| {
+                        Some((gas.trim().parse().ok()?, size.trim().parse().ok()?))
+                    });
+                match weights {
+                    Some((gas, size)) => Ok(Self::Weighted(gas, size)),
+                    None => Err(format!(
+                        "Unknown objective \"{}\" (expected: swaps, gas, size, weighted=<gas weight>,<size weight>)",
+                        s
+                    )),
+                }
+            }
+        }
+    }
+}
+
+impl Objective {
+    fn cost_model(self, block: &IRBlock) -> Box<dyn CostModel> {
+        match self {
+            Self::Swaps => Box::new(SwapCount),
+            Self::Gas => Box::new(GasCost::new(
+                block.sources.iter().map(ValueSource::gas).collect(),
+                &block.graph,
+            )),
+            Self::Size => Box::new(BytecodeSize::new(
+                block.sources.iter().map(ValueSource::byte_size).collect(),
+                &block.graph,
+            )),
+            Self::Weighted(gas_weight, size_weight) => Box::new(Weighted::new(vec![
+                (gas_weight, Self::Gas.cost_model(block)),
+                (size_weight, Self::Size.cost_model(block)),
+            ])),
+        }
+    }
+}
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Emit {
+    Huff,
+    Bytecode,
+}
+
+impl FromStr for Emit {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "huff" => Ok(Self::Huff),
+            "bytecode" => Ok(Self::Bytecode),
+            _ => Err(format!(
+                "Unknown output format \"{}\" (expected: huff, bytecode)",
+                s
+            )),
+        }
+    }
+}
 
-const DEFAULT_GUESSOR_FACTOR: f32 = 0.035;
 const DEFAULT_COMMENT_START: usize = 32;
+const BYTES_PER_MB: usize = 1 << 20;
 
 #[derive(Parser)]
 #[clap(
@@ -24,21 +122,139 @@ struct Cli {
     #[clap(short, long)]
     dijkstra: bool,
 
+    #[clap(
+        short,
+        long,
+        conflicts_with = "dijkstra",
+        help = "Find the optimal schedule like --dijkstra, guided by a lower bound on the remaining swaps"
+    )]
+    bound: bool,
+
     #[clap(short, long, default_value_t=DEFAULT_GUESSOR_FACTOR)]
     guess: f32,
 
+    #[clap(
+        long,
+        num_args = 0..=1,
+        default_missing_value = "10",
+        conflicts_with_all = ["dijkstra", "bound", "guess"],
+        help = "Try decreasing --guess factors for up to this many seconds per function, keeping the cheapest schedule"
+    )]
+    auto_guess: Option<f64>,
+
     #[clap(short, long, default_value_t=DEFAULT_COMMENT_START, help="Character offset at which the // for the stack comment starts")]
     comment: usize,
 
     #[clap(short, long, default_value_t = 4)]
     indent: usize,
 
+    #[clap(
+        long,
+        default_value = "swaps",
+        help = "What to minimize when scheduling: swaps, gas, size or weighted=<gas weight>,<size weight>"
+    )]
+    optimize: Objective,
+
     #[clap(short, long, default_value_t = 1024)]
     max_stack_depth: usize,
 
-    #[clap(short, long, help = "The path to which to write the output")]
+    #[clap(
+        long,
+        help = "Search depth-first with iterative deepening, remembering explored states in at most this many MB"
+    )]
+    max_memory: Option<usize>,
+
+    #[clap(
+        long,
+        help = "Seconds after which to stop searching a block and use the best schedule found so far"
+    )]
+    timeout: Option<f64>,
+
+    #[clap(
+        long,
+        help = "Amount of explored states after which to stop searching a block and use the best schedule found so far"
+    )]
+    max_explored: Option<usize>,
+
+    #[clap(
+        long,
+        default_value_t = 1,
+        conflicts_with = "max_memory",
+        help = "Amount of threads to search with"
+    )]
+    threads: usize,
+
+    #[clap(
+        long,
+        conflicts_with_all = ["max_memory", "threads"],
+        help = "Only keep the best K states after every step of the search, comparing the result with the default scheduler when verbose"
+    )]
+    beam: Option<usize>,
+
+    #[clap(
+        long,
+        help = "Improve the schedule by re-solving every N consecutive steps optimally"
+    )]
+    window: Option<usize>,
+
+    #[clap(
+        long,
+        help = "Inline calls to other BALLS functions unless marked `noinline`"
+    )]
+    inline: bool,
+
+    #[clap(
+        long,
+        help = "Merge identical pure operations and constants so their values can be reused"
+    )]
+    cse: bool,
+
+    #[clap(
+        long,
+        help = "Let the scheduler recompute cheap constant values instead of only duplicating them"
+    )]
+    remat: bool,
+
+    #[clap(
+        long,
+        default_value = "huff",
+        help = "What to output: huff macros or the hex encoded bytecode of every function"
+    )]
+    emit: Emit,
+
+    #[clap(
+        long,
+        help = "File with lines `NAME = 0x<hex>` giving the values of constants and macro arguments or the code of macros to assemble bytecode with"
+    )]
+    link: Option<String>,
+
+    #[clap(
+        long,
+        help = "Map every instruction back to the source, as `@ line:col` comments in Huff or a Solidity-style source map with bytecode"
+    )]
+    source_map: bool,
+
+    #[clap(
+        short,
+        long,
+        help = "The path of the Huff file to splice the output into, every macro replacing the region marked `// balls-insert-start: NAME` to `// balls-insert-end: NAME`"
+    )]
     output_path: Option<String>,
 
+    #[clap(
+        long,
+        requires = "output_path",
+        help = "Append a region to the output file for macros that don't have one"
+    )]
+    append: bool,
+
+    #[clap(
+        long,
+        requires = "output_path",
+        help = "Don't write the output file but fail with a diff if it isn't up to date"
+    )]
+    check: bool,
+
     #[clap(
         short,
         long,
@@ -47,30 +263,93 @@ struct Cli {
     verbose: bool,
 }
 
-const BALLS_INSERT_START: &str = "// balls-insert-start\n";
-const BALLS_INSERT_END: &str = "\n// balls-insert-end";
+impl Cli {
+    fn budget(&self) -> SearchBudget {
+        SearchBudget {
+            timeout: self.timeout.map(Duration::from_secs_f64),
+            max_explored: self.max_explored,
+        }
+    }
+}
+
+/// Schedules using the given scheduler's heuristic, searching in the way selected by the CLI.
+fn schedule_block<S: AStarScheduler>(
+    heuristic: S,
+    args: &Cli,
+    arena: &mut SearchArena,
+    graph: &IRGraph,
+    cost_model: &dyn CostModel,
+    budget: SearchBudget,
+) -> Result<(Vec<Step>, SchedulingTracker), ScheduleError> {
+    if let Some(width) = args.beam {
+        return BeamScheduler::new(heuristic, width).schedule_in(
+            arena,
+            graph,
+            cost_model,
+            args.max_stack_depth,
+            budget,
+        );
+    }
+    match args.max_memory {
+        Some(max_memory) => IterativeDeepening::new(heuristic, max_memory * BYTES_PER_MB)
+            .schedule_in(arena, graph, cost_model, args.max_stack_depth, budget),
+        None if args.threads > 1 => HashDistributed::new(heuristic, args.threads).schedule_in(
+            arena,
+            graph,
+            cost_model,
+            args.max_stack_depth,
+            budget,
+        ),
+        None => heuristic.schedule_in(arena, graph, cost_model, args.max_stack_depth, budget),
+    }
+}
+
+/// Schedules every block with the scheduler selected by the CLI, `guess` being the `Guessooor`
+/// factor to use. On error returns the index of the block that couldn't be scheduled.
+fn schedule_blocks(
+    args: &Cli,
+    arena: &mut SearchArena,
+    blocks: &[IRBlock],
+    cost_models: &[Box<dyn CostModel>],
+    guess: f32,
+    budget: SearchBudget,
+) -> Result<Schedules, (usize, ScheduleError)> {
+    blocks
+        .iter()
+        .zip(cost_models)
+        .enumerate()
+        .map(|(i, (block, cost_model))| {
+            let cost_model = cost_model.as_ref();
+            let scheduled = if args.dijkstra {
+                schedule_block(Dijkstra, args, arena, &block.graph, cost_model, budget)
+            } else if args.bound {
+                schedule_block(LowerBound, args, arena, &block.graph, cost_model, budget)
+            } else {
+                schedule_block(
+                    Guessooor::new(guess),
+                    args,
+                    arena,
+                    &block.graph,
+                    cost_model,
+                    budget,
+                )
+            };
+            scheduled.map_err(|err| (i, err))
+        })
+        .collect()
+}
 
-fn splice_into_huff(path: &str, content_to_inject: String) -> Result<(), String> {
+/// Splices the macros into the Huff file at `path`, returning its current and new contents.
+fn splice_into_huff(
+    path: &str,
+    macros: &[(String, String)],
+    append_missing: bool,
+) -> Result<(String, String), String> {
     let content =
         std::fs::read_to_string(path).map_err(|_| format!("Failed to read file {}", path))?;
-    let start_index = content
-        .find(BALLS_INSERT_START)
-        .ok_or(format!("Could not find \"{}\"", BALLS_INSERT_START))?
-        + BALLS_INSERT_START.len();
-    let end_index = content
-        .find(BALLS_INSERT_END)
-        .ok_or(format!("Could not find \"{}\"", BALLS_INSERT_END))?;
-
-    let new_content = format!(
-        "{}{}{}",
-        &content[..start_index],
-        content_to_inject,
-        &content[end_index..]
-    );
-
-    std::fs::write(path, new_content).map_err(|_| format!("Failed to write file {}", path))?;
-
-    Ok(())
+    let new_content = splice_macros(&content, macros, append_missing)
+        .map_err(|err| format!("Failed to splice into {}: {}", path, err))?;
+    Ok((content, new_content))
 }
 
 fn main() {
@@ -78,93 +357,312 @@ fn main() {
 
     let total = Instant::now();
 
-    assert!(
-        args.max_stack_depth <= 1024,
-        "TODO: Invalid max stack depth of {}",
-        args.max_stack_depth
-    );
-
     let file_path = &args.file_path;
     let src = std::fs::read_to_string(file_path).unwrap();
 
     let start = Instant::now();
-    let lex_out = lexer::lex(src.as_str());
+    let (maybe_spanned_tokens, lex_errs) = lexer::lex(src.as_str());
+
+    let lex_errored = print_errors(&src, file_path, lex_errs, |char_span| char_span.clone());
 
-    // TODO: Proper lexer error handling
-    let spanned_tokens = lex_out.unwrap();
+    // Lexing recovers from errors, still parse what was lexed to report any further errors.
+    let spanned_tokens = maybe_spanned_tokens.unwrap_or_default();
 
     let tokens: Vec<_> = spanned_tokens.iter().map(|t| t.inner.clone()).collect();
 
     let (maybe_ast_nodes, errs) = parser::parse_tokens(tokens.clone());
 
-    let errored = print_errors(&src, file_path, errs, |tok_span| {
+    let parse_errored = print_errors(&src, file_path, errs, |tok_span| {
         resolve_span_span(tok_span, &spanned_tokens)
     });
 
-    if errored {
+    if parse_errored {
         std::process::exit(1);
     }
     let parse_lex_time = start.elapsed().as_secs_f64();
 
     if let Some(ast_nodes) = maybe_ast_nodes {
-        let symbols: Symbols = validate_and_get_symbols(ast_nodes).unwrap();
+        // Imported Huff files are relative to the BALLS file.
+        let import_dir = Path::new(file_path).parent().unwrap_or(Path::new(""));
+        let resolved = resolve_huff_imports(ast_nodes, |path| {
+            std::fs::read_to_string(import_dir.join(path))
+        });
+        let symbols: Symbols = match resolved.and_then(validate_and_get_symbols) {
+            Ok(symbols) => symbols,
+            Err(errs) => {
+                print_semantic_errors(&src, file_path, errs, |tok_span| {
+                    resolve_span_span(tok_span, &spanned_tokens)
+                });
+                std::process::exit(1);
+            }
+        };
 
-        let mut ball_macros: Vec<String> = Vec::new();
+        if lex_errored {
+            std::process::exit(1);
+        }
+
+        let links: Links = match &args.link {
+            Some(path) => std::fs::read_to_string(path)
+                .map_err(|_| format!("Failed to read file {}", path))
+                .and_then(|src| parse_links(&src))
+                .unwrap_or_else(|err| {
+                    eprintln!("Invalid links: {}", err);
+                    std::process::exit(1);
+                }),
+            None => Links::new(),
+        };
+
+        // (function name, output)
+        let mut ball_macros: Vec<(String, String)> = Vec::new();
+        // Shared by the searches of all blocks, only allocating anew when a search needs more
+        // memory than any before.
+        let mut arena = SearchArena::new();
 
         let schedule_summaries: Vec<_> = symbols
             .values()
             .filter_map(|symbol| match &symbol.inner {
-                Symbol::Function(func) => Some(func),
+                Symbol::Function(func) => Some((func, &symbol.span)),
                 _ => None,
             })
-            .map(|func| {
+            .map(|(func, func_span)| {
                 let start = Instant::now();
-                let (ir_graph, value_sources, assignments) = gen_ir(func, &symbols);
+                let blocks = gen_ir(func, &symbols, args.inline).unwrap_or_else(|err| {
+                    print_semantic_errors(&src, file_path, vec![err], |tok_span| {
+                        resolve_span_span(tok_span, &spanned_tokens)
+                    });
+                    std::process::exit(1);
+                });
+                let blocks: Vec<_> = blocks
+                    .into_iter()
+                    .map(|block| {
+                        let mut block = if args.cse {
+                            eliminate_common_subexpressions(block, &symbols)
+                        } else {
+                            block
+                        };
+                        if args.remat {
+                            mark_rematerializable(&mut block, &symbols);
+                        }
+                        block
+                    })
+                    .collect();
                 let preprocessing_time = start.elapsed().as_secs_f64();
 
-                let (steps, tracker) = if args.dijkstra {
-                    Dijkstra.schedule(&ir_graph, args.max_stack_depth)
-                } else {
-                    Guessooor::new(args.guess).schedule(&ir_graph, args.max_stack_depth)
+                let cost_models: Vec<_> = blocks
+                    .iter()
+                    .map(|block| args.optimize.cost_model(block))
+                    .collect();
+                let scheduled = match args.auto_guess {
+                    Some(secs) => tune_guess(
+                        Duration::from_secs_f64(secs),
+                        args.budget(),
+                        |guess, budget| {
+                            schedule_blocks(&args, &mut arena, &blocks, &cost_models, guess, budget)
+                        },
+                    )
+                    .map(|(schedules, tuned)| (schedules, Some(tuned))),
+                    None => schedule_blocks(
+                        &args,
+                        &mut arena,
+                        &blocks,
+                        &cost_models,
+                        args.guess,
+                        args.budget(),
+                    )
+                    .map(|schedules| (schedules, None)),
+                };
+                let (schedules, tuned_guess) = scheduled.unwrap_or_else(|(i, err)| {
+                    print_schedule_error(
+                        &src,
+                        file_path,
+                        func,
+                        func_span,
+                        &blocks[i],
+                        &err,
+                        |tok_span| resolve_span_span(tok_span, &spanned_tokens),
+                    );
+                    std::process::exit(1);
+                });
+                let guess = tuned_guess.map_or(args.guess, |tuned| tuned.factor);
+
+                let (block_steps, trackers): (Vec<_>, Vec<_>) = schedules
+                    .into_iter()
+                    .zip(blocks.iter().zip(&cost_models))
+                    .enumerate()
+                    .map(|(i, ((steps, mut tracker), (block, cost_model)))| {
+                        let steps = match args.window {
+                            Some(window_size) => {
+                                let (steps, savings) = reoptimize_windows(
+                                    &block.graph,
+                                    cost_model.as_ref(),
+                                    steps,
+                                    window_size,
+                                    args.max_stack_depth,
+                                    &mut arena,
+                                );
+                                tracker.record_window_savings(savings);
+                                steps
+                            }
+                            None => steps,
+                        };
+                        if args.beam.is_some() && args.verbose {
+                            if let Ok((_, guessooor)) = Guessooor::new(guess).schedule_in(
+                                &mut arena,
+                                &block.graph,
+                                cost_model.as_ref(),
+                                args.max_stack_depth,
+                                args.budget(),
+                            ) {
+                                tracker.record_comparison("Guessooor", guessooor.final_cost());
+                            }
+                        }
+                        let name = block.label.clone().unwrap_or_else(|| format!("#{}", i));
+                        (steps, (name, tracker))
+                    })
+                    .unzip();
+
+                let output = match args.emit {
+                    Emit::Huff => {
+                        let locate = |tok_span: &_| {
+                            let span = resolve_span_span(tok_span, &spanned_tokens);
+                            let (line, col) = line_col(&src, span.start);
+                            format!("{}:{}", line, col)
+                        };
+                        huff_formatter::format_with_stack_comments(
+                            func,
+                            &symbols,
+                            &blocks,
+                            block_steps,
+                            args.comment,
+                            args.indent,
+                            args.source_map.then_some(&locate as &dyn Fn(&_) -> String),
+                        )
+                    }
+                    Emit::Bytecode => {
+                        let bytecode = assemble(&symbols, &blocks, block_steps, &links)
+                            .unwrap_or_else(|err| {
+                                eprintln!("Failed to assemble {}: {}", func.ident, err);
+                                std::process::exit(1);
+                            });
+                        let mut output = format!("{}: 0x{}", func.ident, bytecode.code);
+                        if !bytecode.relocations.is_empty() {
+                            let relocations: Vec<_> = bytecode
+                                .relocations
+                                .iter()
+                                .map(|offset| format!("{:#x}", offset))
+                                .collect();
+                            output.push_str(&format!(
+                                "\n{} relocations: {}",
+                                func.ident,
+                                relocations.join(",")
+                            ));
+                        }
+                        if args.source_map {
+                            let spans: Vec<_> = bytecode
+                                .spans
+                                .iter()
+                                .map(|tok_span| {
+                                    tok_span.as_ref().map(|tok_span| {
+                                        byte_span(
+                                            &src,
+                                            &resolve_span_span(tok_span, &spanned_tokens),
+                                        )
+                                    })
+                                })
+                                .collect();
+                            output.push_str(&format!(
+                                "\n{} source map: {}",
+                                func.ident,
+                                solidity_source_map(&spans)
+                            ));
+                        }
+                        output
+                    }
                 };
 
-                let output = huff_formatter::format_with_stack_comments(
-                    func,
-                    &symbols,
-                    &ir_graph,
-                    value_sources.as_slice(),
-                    assignments.as_slice(),
-                    steps,
-                    args.comment,
-                    args.indent,
-                );
-
-                ball_macros.push(output);
+                ball_macros.push((func.ident.clone(), output));
 
-                (func.ident.clone(), tracker, preprocessing_time)
+                (
+                    func.ident.clone(),
+                    trackers,
+                    preprocessing_time,
+                    tuned_guess,
+                )
             })
             .collect();
 
-        let full_balls = ball_macros.join("\n\n");
-        match args.output_path {
-            None => println!("{}", full_balls),
+        let separator = match args.emit {
+            Emit::Huff => "\n\n",
+            Emit::Bytecode => "\n",
+        };
+        let joined_output = || {
+            ball_macros
+                .iter()
+                .map(|(_, output)| output.as_str())
+                .collect::<Vec<_>>()
+                .join(separator)
+        };
+        match &args.output_path {
+            None => println!("{}", joined_output()),
             Some(output_path) => {
-                splice_into_huff(&output_path, full_balls).unwrap();
-                if args.verbose {
-                    println!("✅ Successfully inserted result into {}\n", &output_path);
+                let contents = match args.emit {
+                    Emit::Huff => splice_into_huff(output_path, &ball_macros, args.append),
+                    Emit::Bytecode => Ok((
+                        std::fs::read_to_string(output_path).unwrap_or_default(),
+                        joined_output() + "\n",
+                    )),
+                };
+                let (content, new_content) = contents.unwrap_or_else(|err| {
+                    eprintln!("{}", err);
+                    std::process::exit(1);
+                });
+                if args.check {
+                    let diff = unified_diff(
+                        &content,
+                        &new_content,
+                        output_path,
+                        &format!("{} (regenerated)", output_path),
+                        3,
+                    );
+                    if !diff.is_empty() {
+                        print!("{}", diff);
+                        eprintln!("❌ {} is out of date", output_path);
+                        std::process::exit(1);
+                    }
+                    if args.verbose {
+                        println!("✅ {} is up to date\n", output_path);
+                    }
+                } else {
+                    std::fs::write(output_path, new_content).unwrap_or_else(|_| {
+                        eprintln!("Failed to write file {}", output_path);
+                        std::process::exit(1);
+                    });
+                    if args.verbose {
+                        println!("✅ Successfully inserted result into {}\n", output_path);
+                    }
                 }
             }
         }
 
         if args.verbose {
             println!("\nLexing + parsing: {}", parse_lex_time.humanize_seconds());
-            for (name, tracker, preprocessing_time) in schedule_summaries {
+            for (name, trackers, preprocessing_time, tuned_guess) in schedule_summaries {
                 println!("{}:", name);
                 println!(
                     "  Macro pre-processing: {}",
                     preprocessing_time.humanize_seconds()
                 );
-                tracker.report(2);
+                if let Some(TunedGuess { factor, tried }) = tuned_guess {
+                    println!("  Guess factor: {} (best of {} tried)", factor, tried);
+                }
+                if let [(_, tracker)] = trackers.as_slice() {
+                    tracker.report(2);
+                } else {
+                    for (block_name, tracker) in trackers {
+                        println!("  Block {}:", block_name);
+                        tracker.report(4);
+                    }
+                }
             }
         }
 
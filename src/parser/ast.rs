@@ -3,7 +3,7 @@ use std::boxed::Box;
 use crate::parser::types::Spanned;
 use num_bigint::BigUint;
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub enum MacroArg {
     ArgRef(String),
     Num(BigUint),
@@ -25,9 +25,20 @@ impl MacroArg {
     }
 }
 
+/// Whether a call to a BALLS function should be spliced into the caller's IR or invoked as a macro.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Inlining {
+    /// Defer to the function's annotation or the global default.
+    #[default]
+    Default,
+    Always,
+    Never,
+}
+
 #[derive(Clone, Debug)]
 pub enum Expr {
     Call {
+        inlining: Inlining,
         ident: Spanned<String>,
         macro_args: Spanned<Vec<Spanned<MacroArg>>>,
         stack_args: Spanned<Box<Vec<Spanned<Expr>>>>,
@@ -37,11 +48,36 @@ pub enum Expr {
 }
 
 #[derive(Clone, Debug)]
-pub struct Statement {
-    pub ident: Option<Spanned<String>>,
+pub struct Assignment {
+    /// Identifiers the outputs of the expression are assigned to, empty if the statement's result
+    /// is discarded. More than one identifier destructures a multi-output call.
+    pub idents: Vec<Spanned<String>>,
     pub expr: Spanned<Expr>,
 }
 
+#[derive(Clone, Debug)]
+pub enum Statement {
+    Assign(Assignment),
+    If {
+        condition: Spanned<Expr>,
+        then_body: Vec<Statement>,
+        else_body: Option<Vec<Statement>>,
+    },
+    Loop {
+        /// Variables carried from one iteration to the next, top of the stack first. The body
+        /// leaves them in the same layout it received them in.
+        carried: Vec<Spanned<String>>,
+        condition: Spanned<Expr>,
+        body: Vec<Statement>,
+    },
+}
+
+impl Statement {
+    pub fn is_control_flow(&self) -> bool {
+        !matches!(self, Self::Assign(_))
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct HuffMacro {
     pub ident: String,
@@ -52,8 +88,18 @@ pub struct HuffMacro {
     pub writes: Vec<Spanned<String>>,
 }
 
+/// `extern` declaration without a `stack(..)`, annotating a macro imported from a Huff file.
+#[derive(Clone, Debug)]
+pub struct HuffMacroDeps {
+    pub ident: String,
+    pub macro_args: Vec<Spanned<String>>,
+    pub reads: Vec<Spanned<String>>,
+    pub writes: Vec<Spanned<String>>,
+}
+
 #[derive(Clone, Debug)]
 pub struct Function {
+    pub inlining: Inlining,
     pub ident: String,
     pub macro_args: Vec<Spanned<String>>,
     pub inputs: Vec<Spanned<String>>,
@@ -69,5 +115,8 @@ pub enum Ast {
     Const(String),
     Function(Function),
     HuffMacro(HuffMacro),
+    HuffMacroDeps(HuffMacroDeps),
+    /// Path of a Huff file to import macros and constants from, relative to the BALLS file.
+    HuffImport(Spanned<String>),
     Error,
 }
@@ -1,14 +1,22 @@
-use crate::parser::{tokens::Token, types::Span};
+use crate::parser::ast::{Function, Statement};
+use crate::parser::types::Span;
+use crate::scheduling::ScheduleError;
+use crate::transformer::analysis::SemanticError;
+use crate::transformer::ir_gen::{IRBlock, ValueSource};
 use ariadne::{Color, Fmt, Label, Report, ReportKind, Source};
 use chumsky::error::{Simple, SimpleReason};
+use std::fmt::Display;
+use std::hash::Hash;
 
-pub fn print_errors<F>(
+/// Prints lexer (`T = char`) or parser (`T = Token`) errors, returning whether there were any.
+pub fn print_errors<T, F>(
     src: &str,
     file_path: &str,
-    errs: Vec<Simple<Token>>,
+    errs: Vec<Simple<T>>,
     mut token_span_resolver: F,
 ) -> bool
 where
+    T: Display + Hash + Eq,
     F: FnMut(&Span) -> Span,
 {
     let errored = !errs.is_empty();
@@ -87,3 +95,287 @@ where
 
     errored
 }
+
+fn plural(count: usize, noun: &str) -> String {
+    if count == 1 {
+        format!("{} {}", count, noun)
+    } else {
+        format!("{} {}s", count, noun)
+    }
+}
+
+pub fn print_semantic_errors<F>(
+    src: &str,
+    file_path: &str,
+    errs: Vec<SemanticError>,
+    mut token_span_resolver: F,
+) -> bool
+where
+    F: FnMut(&Span) -> Span,
+{
+    let errored = !errs.is_empty();
+
+    errs.into_iter().for_each(|err| {
+        // Standard library symbols have an empty span, they have no location to point to.
+        let mut label = |span: &Span, msg: String, color: Color| {
+            (!span.is_empty()).then(|| (token_span_resolver(span), msg, color))
+        };
+
+        let (msg, labels) = match &err {
+            SemanticError::DuplicateTopLevelIdentifier(ident, first_span, span) => (
+                format!("Duplicate top-level identifier {}", ident.fg(Color::Red)),
+                if first_span.is_empty() {
+                    vec![label(
+                        span,
+                        format!("{} is already defined by the standard library", ident),
+                        Color::Red,
+                    )]
+                } else {
+                    vec![
+                        label(first_span, "First defined here".into(), Color::Yellow),
+                        label(span, "Redefined here".into(), Color::Red),
+                    ]
+                },
+            ),
+            SemanticError::DuplicateIdentifier(ident_type, ident) => (
+                format!("Duplicate {} {}", ident_type, (&ident.inner).fg(Color::Red)),
+                vec![label(
+                    &ident.span,
+                    format!("{} already used as {}", ident.inner, ident_type),
+                    Color::Red,
+                )],
+            ),
+            SemanticError::AssigningToImmutableTopLevel(span) => (
+                "Cannot assign to a top-level identifier or macro argument".into(),
+                vec![label(span, "Immutable identifier".into(), Color::Red)],
+            ),
+            SemanticError::UndeclaredIdentifier(expected, ident) => (
+                format!("Undeclared identifier {}", (&ident.inner).fg(Color::Red)),
+                vec![label(
+                    &ident.span,
+                    format!("Not found in scope (expected: {})", expected),
+                    Color::Red,
+                )],
+            ),
+            SemanticError::CallingNonCallable(ident, def_span) => (
+                format!("{} is not callable", (&ident.inner).fg(Color::Red)),
+                vec![
+                    label(&ident.span, "Called here".into(), Color::Red),
+                    label(
+                        def_span,
+                        format!("{} defined here", ident.inner),
+                        Color::Yellow,
+                    ),
+                ],
+            ),
+            SemanticError::CallArgumentMismatch(expected, actual, arg_type, ident, span) => (
+                format!(
+                    "{} expects {}, got {}",
+                    ident.fg(Color::Red),
+                    plural(*expected, &format!("{} argument", arg_type)),
+                    actual
+                ),
+                vec![label(
+                    span,
+                    format!(
+                        "Supplied {}",
+                        plural(*actual, &format!("{} argument", arg_type))
+                    ),
+                    Color::Red,
+                )],
+            ),
+            SemanticError::NoOutputFromCall(callable_type, ident, span) => (
+                format!(
+                    "{} {} has no output to be used as a value",
+                    callable_type,
+                    ident.fg(Color::Red)
+                ),
+                vec![label(span, "Has no output".into(), Color::Red)],
+            ),
+            SemanticError::OutputCountMismatch(expected, actual, callable_type, ident, span) => (
+                format!(
+                    "{} {} returns {}, expected {}",
+                    callable_type,
+                    ident.fg(Color::Red),
+                    plural(*actual, "value"),
+                    expected
+                ),
+                vec![label(
+                    span,
+                    format!("Returns {}", plural(*actual, "value")),
+                    Color::Red,
+                )],
+            ),
+            SemanticError::ReadAndWrite(read, write) => (
+                format!(
+                    "{} is both read and written, writes imply reads",
+                    (&read.inner).fg(Color::Red)
+                ),
+                vec![
+                    label(&read.span, "Read here".into(), Color::Yellow),
+                    label(&write.span, "Written here".into(), Color::Red),
+                ],
+            ),
+            SemanticError::InliningNonFunction(callable_type, ident) => (
+                format!(
+                    "Only functions can be inlined, not {} {}",
+                    callable_type,
+                    (&ident.inner).fg(Color::Red)
+                ),
+                vec![label(&ident.span, "Cannot be inlined".into(), Color::Red)],
+            ),
+            SemanticError::InliningControlFlow(ident) => (
+                format!(
+                    "{} contains control flow and cannot be inlined",
+                    (&ident.inner).fg(Color::Red)
+                ),
+                vec![label(&ident.span, "Cannot be inlined".into(), Color::Red)],
+            ),
+            SemanticError::HuffImportFailed(path, reason) => (
+                format!("Failed to import {}: {}", (&path.inner).fg(Color::Red), reason),
+                vec![label(&path.span, "Imported here".into(), Color::Red)],
+            ),
+            SemanticError::MissingStackIO(ident, span) => (
+                format!(
+                    "extern {} has no stack(..) and isn't defined in an imported Huff file",
+                    ident.fg(Color::Red)
+                ),
+                vec![label(span, "Missing stack(..)".into(), Color::Red)],
+            ),
+            SemanticError::ImportedStackMismatch(ident, declared, imported, span, import_span) => (
+                format!(
+                    "extern {} is declared with stack({}, {}), the imported macro has takes({}) returns({})",
+                    ident.fg(Color::Red),
+                    declared.0,
+                    declared.1,
+                    imported.0,
+                    imported.1
+                ),
+                vec![
+                    label(span, "Declared here".into(), Color::Red),
+                    label(import_span, "Imported here".into(), Color::Yellow),
+                ],
+            ),
+            SemanticError::ImportedArgumentMismatch(ident, declared, imported, span, import_span) => (
+                format!(
+                    "extern {} is declared with {}, the imported macro takes {}",
+                    ident.fg(Color::Red),
+                    plural(*declared, "macro argument"),
+                    imported
+                ),
+                vec![
+                    label(span, "Declared here".into(), Color::Red),
+                    label(import_span, "Imported here".into(), Color::Yellow),
+                ],
+            ),
+        };
+
+        let labels: Vec<_> = labels.into_iter().flatten().collect();
+        let offset = labels.first().map_or(0, |(span, _, _)| span.start);
+
+        Report::build(ReportKind::Error, &file_path, offset)
+            .with_message(msg)
+            .with_labels(labels.into_iter().map(|(span, msg, color)| {
+                Label::new((&file_path, span))
+                    .with_message(msg)
+                    .with_color(color)
+            }))
+            .finish()
+            .print((&file_path, Source::from(&src)))
+            .expect("failed to print error report");
+    });
+
+    errored
+}
+
+/// Finds where a variable of the function is defined (as an input, output or assignment).
+fn find_variable_span<'a>(func: &'a Function, name: &str) -> Option<&'a Span> {
+    fn in_body<'a>(body: &'a [Statement], name: &str) -> Option<&'a Span> {
+        body.iter().find_map(|statement| match statement {
+            Statement::Assign(assignment) => assignment
+                .idents
+                .iter()
+                .find(|ident| ident.inner == name)
+                .map(|ident| &ident.span),
+            Statement::If {
+                then_body,
+                else_body,
+                ..
+            } => in_body(then_body, name)
+                .or_else(|| else_body.as_deref().and_then(|body| in_body(body, name))),
+            Statement::Loop { carried, body, .. } => carried
+                .iter()
+                .find(|ident| ident.inner == name)
+                .map(|ident| &ident.span)
+                .or_else(|| in_body(body, name)),
+        })
+    }
+
+    func.inputs
+        .iter()
+        .chain(&func.outputs)
+        .find(|ident| ident.inner == name)
+        .map(|ident| &ident.span)
+        .or_else(|| in_body(&func.body, name))
+}
+
+/// Prints an error that occurred while scheduling one of the blocks of `func`.
+pub fn print_schedule_error<F>(
+    src: &str,
+    file_path: &str,
+    func: &Function,
+    func_span: &Span,
+    block: &IRBlock,
+    err: &ScheduleError,
+    mut token_span_resolver: F,
+) where
+    F: FnMut(&Span) -> Span,
+{
+    let block_name = block
+        .label
+        .as_ref()
+        .map_or(String::new(), |label| format!(" (block {})", label));
+    let func_span = token_span_resolver(func_span);
+    let mut labels = vec![(
+        func_span.clone(),
+        format!("While scheduling {}", (&func.ident).fg(Color::Red)),
+        Color::Red,
+    )];
+
+    if let ScheduleError::SwapTooDeep(_, id) = err {
+        let name = block
+            .assignments
+            .iter()
+            .find(|(_, assigned)| assigned == id)
+            .map(|(ident, _)| ident.as_str())
+            .or_else(|| match &block.sources[*id] {
+                ValueSource::TopLevelInput(ident) => Some(ident),
+                _ => None,
+            });
+        if let Some((name, span)) =
+            name.and_then(|name| Some((name, find_variable_span(func, name)?)))
+        {
+            labels.push((
+                token_span_resolver(span),
+                format!("{} cannot be reached", name.fg(Color::Yellow)),
+                Color::Yellow,
+            ));
+        }
+    }
+
+    Report::build(ReportKind::Error, &file_path, func_span.start)
+        .with_message(format!(
+            "Failed to schedule {}{}: {}",
+            (&func.ident).fg(Color::Red),
+            block_name,
+            err
+        ))
+        .with_labels(labels.into_iter().map(|(span, msg, color)| {
+            Label::new((&file_path, span))
+                .with_message(msg)
+                .with_color(color)
+        }))
+        .finish()
+        .print((&file_path, Source::from(&src)))
+        .expect("failed to print error report");
+}
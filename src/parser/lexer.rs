@@ -1,3 +1,4 @@
+use chumsky::error::SimpleReason;
 use chumsky::prelude::*;
 use num_bigint::BigUint;
 
@@ -48,6 +49,15 @@ fn number() -> impl Parser<char, Token, Error = Simple<char>> {
     hexadecimal.or(decimal)
 }
 
+fn string() -> impl Parser<char, Token, Error = Simple<char>> {
+    just('"')
+        .ignore_then(filter(|c: &char| c != &'"' && c != &'\n').repeated())
+        .then_ignore(just('"'))
+        .collect()
+        .map(Token::Str)
+        .labelled("string")
+}
+
 fn ident() -> impl Parser<char, Token, Error = Simple<char>> {
     filter(|c: &char| c.is_ascii_alphabetic() || c == &'_')
         .map(Some)
@@ -64,30 +74,59 @@ fn ident() -> impl Parser<char, Token, Error = Simple<char>> {
             "writes" => Token::Writes,
             "extern" => Token::External,
             "const" => Token::Const,
+            "inline" => Token::Inline,
+            "noinline" => Token::NoInline,
+            "import" => Token::Import,
+            "if" => Token::If,
+            "else" => Token::Else,
+            "loop" => Token::Loop,
+            "while" => Token::While,
             _ => Token::Ident(name),
         })
 }
 
 pub fn lexer() -> impl Parser<char, Vec<Spanned<Token>>, Error = Simple<char>> {
-    let single_line_comment = just("//").then(take_until(text::newline())).to(());
+    let single_line_comment = just("//")
+        .then(take_until(text::newline().or(end())))
+        .to(());
 
-    let multi_line_comment = just("/*").then(take_until(just("*/"))).to(());
+    // Reports an unterminated comment instead of failing to lex the rest of the file.
+    let multi_line_comment = just("/*")
+        .ignore_then(take_until(just("*/").to(true).or(end().to(false))))
+        .validate(|(_, closed), span, emit| {
+            if !closed {
+                emit(Simple::custom(span, "Unterminated block comment"))
+            }
+        });
 
     let comment = single_line_comment
         .or(multi_line_comment)
         .padded()
         .labelled("comment");
 
-    let token = symbols().or(number()).or(ident());
+    let token = symbols().or(number()).or(string()).or(ident());
 
     token
         .map_with_span(Spanned::new)
         .padded_by(comment.repeated())
         .padded()
+        // Skip unexpected characters, continuing to lex after them.
+        .recover_with(skip_then_retry_until([]))
         .repeated()
         .then_ignore(end())
 }
 
-pub fn lex(source: &str) -> Result<Vec<Spanned<Token>>, Vec<Simple<char>>> {
-    lexer().parse(source)
+pub fn lex(source: &str) -> (Option<Vec<Spanned<Token>>>, Vec<Simple<char>>) {
+    let (tokens, errs) = lexer().parse_recovery(source);
+    // The characters that could've been expected are rarely helpful, just point out the bad one.
+    let errs = errs
+        .into_iter()
+        .map(|err| match (err.reason(), err.found()) {
+            (SimpleReason::Unexpected, Some(found)) => {
+                Simple::custom(err.span(), format!("Unexpected character '{}'", found))
+            }
+            _ => err,
+        })
+        .collect();
+    (tokens, errs)
 }
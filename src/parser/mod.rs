@@ -1,6 +1,7 @@
 pub mod ast;
 pub mod error_printing;
 pub mod lexer;
+#[allow(clippy::module_inception)]
 pub mod parser;
 pub mod tokens;
 pub mod types;
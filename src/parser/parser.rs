@@ -3,25 +3,76 @@ use chumsky::prelude::*;
 use num_bigint::{BigUint, TryFromBigIntError};
 
 use crate::parser::{
-    ast::{Ast, Expr, Function, HuffMacro, MacroArg, Statement},
+    ast::{
+        Assignment, Ast, Expr, Function, HuffMacro, HuffMacroDeps, Inlining, MacroArg, Statement,
+    },
     tokens::Token,
-    types::Spanned,
+    types::{Span, Spanned},
 };
 
-fn ident() -> impl Parser<Token, String, Error = Simple<Token>> {
+/// Boxed `Simple<Token>`, keeping the `Err` variant of the parsers' intermediate results small.
+#[derive(Debug, Clone)]
+pub struct ParseError(Box<Simple<Token>>);
+
+impl ParseError {
+    fn custom(span: Span, msg: impl ToString) -> Self {
+        Self(Box::new(Simple::custom(span, msg)))
+    }
+}
+
+impl chumsky::Error<Token> for ParseError {
+    type Span = Span;
+    type Label = &'static str;
+
+    fn expected_input_found<Iter: IntoIterator<Item = Option<Token>>>(
+        span: Span,
+        expected: Iter,
+        found: Option<Token>,
+    ) -> Self {
+        Self(Box::new(Simple::expected_input_found(
+            span, expected, found,
+        )))
+    }
+
+    fn unclosed_delimiter(
+        unclosed_span: Span,
+        unclosed: Token,
+        span: Span,
+        expected: Token,
+        found: Option<Token>,
+    ) -> Self {
+        Self(Box::new(Simple::unclosed_delimiter(
+            unclosed_span,
+            unclosed,
+            span,
+            expected,
+            found,
+        )))
+    }
+
+    fn with_label(self, label: &'static str) -> Self {
+        Self(Box::new(self.0.with_label(label)))
+    }
+
+    fn merge(self, other: Self) -> Self {
+        Self(Box::new(self.0.merge(*other.0)))
+    }
+}
+
+fn ident() -> impl Parser<Token, String, Error = ParseError> {
     select! { Token::Ident(ident) => ident }
 }
 
-fn dependency_definition() -> impl Parser<Token, Ast, Error = Simple<Token>> {
+fn dependency_definition() -> impl Parser<Token, Ast, Error = ParseError> {
     just(Token::Dependency).ignore_then(ident().map(Ast::Dependency))
 }
 
-fn stack_size() -> impl Parser<Token, u16, Error = Simple<Token>> {
+fn stack_size() -> impl Parser<Token, u16, Error = ParseError> {
     select! { Token::Number(num) => num }.validate(|num, span, emit| match num.try_into() {
         Ok(lol) => lol,
         Err(err) => {
             let err: TryFromBigIntError<BigUint> = err;
-            emit(Simple::custom(
+            emit(ParseError::custom(
                 span,
                 format!(
                     "Number {} exceeds max valid stack size specifier (max: {})",
@@ -34,9 +85,7 @@ fn stack_size() -> impl Parser<Token, u16, Error = Simple<Token>> {
     })
 }
 
-fn dependency_list(
-    token: Token,
-) -> impl Parser<Token, Vec<Spanned<String>>, Error = Simple<Token>> {
+fn dependency_list(token: Token) -> impl Parser<Token, Vec<Spanned<String>>, Error = ParseError> {
     just(token)
         .ignore_then(
             ident()
@@ -51,8 +100,8 @@ fn recover_for_delimiters<T, const N: usize>(
     open: Token,
     close: Token,
     other_delims: [(Token, Token); N],
-    parser: impl Parser<Token, T, Error = Simple<Token>>,
-) -> impl Parser<Token, Result<T, ()>, Error = Simple<Token>> {
+    parser: impl Parser<Token, T, Error = ParseError>,
+) -> impl Parser<Token, Result<T, ()>, Error = ParseError> {
     parser
         .delimited_by(just(open.clone()), just(close.clone()))
         .map(Ok)
@@ -60,8 +109,8 @@ fn recover_for_delimiters<T, const N: usize>(
 }
 
 fn recover_for_round_delimited<T>(
-    parser: impl Parser<Token, T, Error = Simple<Token>>,
-) -> impl Parser<Token, Result<T, ()>, Error = Simple<Token>> {
+    parser: impl Parser<Token, T, Error = ParseError>,
+) -> impl Parser<Token, Result<T, ()>, Error = ParseError> {
     recover_for_delimiters(
         Token::OpenRound,
         Token::CloseRound,
@@ -74,7 +123,7 @@ fn recover_for_round_delimited<T>(
     )
 }
 
-fn stack_io() -> impl Parser<Token, Result<(u16, u16), ()>, Error = Simple<Token>> {
+fn stack_io() -> impl Parser<Token, Result<(u16, u16), ()>, Error = ParseError> {
     just(Token::Stack).ignore_then(recover_for_round_delimited(
         stack_size()
             .then_ignore(just(Token::Comma))
@@ -82,25 +131,31 @@ fn stack_io() -> impl Parser<Token, Result<(u16, u16), ()>, Error = Simple<Token
     ))
 }
 
-fn interactions() -> impl Parser<
-    Token,
-    Result<(u16, u16, Vec<Spanned<String>>, Vec<Spanned<String>>), ()>,
-    Error = Simple<Token>,
-> {
+/// Stack inputs and outputs (optional for macros imported from a Huff file), reads and writes.
+type Interactions = (
+    Option<(u16, u16)>,
+    Vec<Spanned<String>>,
+    Vec<Spanned<String>>,
+);
+
+fn interactions() -> impl Parser<Token, Result<Interactions, ()>, Error = ParseError> {
     stack_io()
+        .or_not()
         .then(dependency_list(Token::Reads))
         .then(dependency_list(Token::Writes))
         .map(|((stack_io_res, reads), writes)| {
-            stack_io_res.map(|(stack_in, stack_out)| (stack_in, stack_out, reads, writes))
+            stack_io_res
+                .transpose()
+                .map(|stack_io| (stack_io, reads, writes))
         })
 }
 
-fn number() -> impl Parser<Token, BigUint, Error = Simple<Token>> {
+fn number() -> impl Parser<Token, BigUint, Error = ParseError> {
     select! { Token::Number(num) => num }.validate(|num, span, emit| {
         if num.bits() <= 256 {
             num
         } else {
-            emit(Simple::custom(
+            emit(ParseError::custom(
                 span,
                 format!("Expression constant 0x{:x} larger than 32-bytes", num),
             ));
@@ -109,13 +164,20 @@ fn number() -> impl Parser<Token, BigUint, Error = Simple<Token>> {
     })
 }
 
-fn macro_arg() -> impl Parser<Token, MacroArg, Error = Simple<Token>> {
+fn macro_arg() -> impl Parser<Token, MacroArg, Error = ParseError> {
     ident()
         .map(MacroArg::ArgRef)
         .or(number().map(MacroArg::Num))
 }
 
-fn expression() -> impl Parser<Token, Spanned<Expr>, Error = Simple<Token>> {
+fn inlining() -> impl Parser<Token, Inlining, Error = ParseError> {
+    just(Token::Inline)
+        .to(Inlining::Always)
+        .or(just(Token::NoInline).to(Inlining::Never))
+        .or_default()
+}
+
+fn expression() -> impl Parser<Token, Spanned<Expr>, Error = ParseError> {
     recursive(|expr| {
         let macro_args = macro_arg()
             .map_with_span(Spanned::new)
@@ -128,11 +190,12 @@ fn expression() -> impl Parser<Token, Spanned<Expr>, Error = Simple<Token>> {
             .delimited_by(just(Token::OpenRound), just(Token::CloseRound))
             .map(Box::new)
             .map_with_span(Spanned::new);
-        let call = ident()
-            .map_with_span(Spanned::new)
+        let call = inlining()
+            .then(ident().map_with_span(Spanned::new))
             .then(macro_args)
             .then(stack_args)
-            .map(|((ident, macro_args), stack_args)| Expr::Call {
+            .map(|(((inlining, ident), macro_args), stack_args)| Expr::Call {
+                inlining,
                 ident,
                 macro_args: macro_args.map(|inner| inner.unwrap_or_default()),
                 stack_args,
@@ -145,32 +208,92 @@ fn expression() -> impl Parser<Token, Spanned<Expr>, Error = Simple<Token>> {
     })
 }
 
-fn statement() -> impl Parser<Token, Statement, Error = Simple<Token>> {
-    // Parses "my_var ="
-    let var_assign = ident()
+fn assignment() -> impl Parser<Token, Assignment, Error = ParseError> {
+    // Parses "my_var" or "(a, b)"
+    let single_var = ident().map_with_span(Spanned::new).map(|var| vec![var]);
+    let var_tuple = ident()
+        .map_with_span(Spanned::new)
+        .list()
+        .delimited_by(just(Token::OpenRound), just(Token::CloseRound));
+
+    // Parses "my_var =" or "(a, b) ="
+    let var_assign = single_var
+        .or(var_tuple)
         .then_ignore(just(Token::Assign))
-        .or_not()
-        .map_with_span(|maybe_var, span| maybe_var.map(|ident| Spanned::new(ident, span)));
+        .or_default();
 
-    // Parses "sstore(caller(), add(sload(caller()), x))" or
-    // "wow = lmao(x, d)"
+    // Parses "sstore(caller(), add(sload(caller()), x))", "wow = lmao(x, d)" or
+    // "(q, r) = DIVMOD(x, y)"
     var_assign
         .then(expression())
-        .map(|(ident, expr)| Statement { ident, expr })
+        .map(|(idents, expr)| Assignment { idents, expr })
         .validate(|stated, span, emit| {
-            if stated.ident.is_none() && !matches!(stated.expr.inner, Expr::Call { .. }) {
-                emit(Simple::custom(
+            let is_call = matches!(stated.expr.inner, Expr::Call { .. });
+            if stated.idents.is_empty() && !is_call {
+                emit(ParseError::custom(
                     span,
                     "Top-level expression not allowed".to_string(),
                 ))
+            } else if stated.idents.len() > 1 && !is_call {
+                emit(ParseError::custom(
+                    span,
+                    "Only calls can be destructured into multiple variables".to_string(),
+                ))
             }
             stated
         })
 }
 
-fn function_definition() -> impl Parser<Token, Ast, Error = Simple<Token>> {
-    // fn TRANSFER
-    let macro_def = just(Token::Fn).ignore_then(ident());
+fn statement() -> impl Parser<Token, Statement, Error = ParseError> {
+    recursive(|statement| {
+        let body = statement
+            .repeated()
+            .delimited_by(just(Token::OpenCurly), just(Token::CloseCurly));
+
+        // Parses "if cond { ... } else { ... }", "else if" chains are sugar for a nested if in the
+        // else body.
+        let if_statement = recursive(|if_statement| {
+            just(Token::If)
+                .ignore_then(expression())
+                .then(body.clone())
+                .then(
+                    just(Token::Else)
+                        .ignore_then(body.clone().or(if_statement.map(|nested| vec![nested])))
+                        .or_not(),
+                )
+                .map(|((condition, then_body), else_body)| Statement::If {
+                    condition,
+                    then_body,
+                    else_body,
+                })
+        });
+
+        // Parses "loop (end_ptr, ptr) while lt(ptr, end_ptr) { ... }"
+        let loop_statement = just(Token::Loop)
+            .ignore_then(
+                ident()
+                    .map_with_span(Spanned::new)
+                    .list()
+                    .delimited_by(just(Token::OpenRound), just(Token::CloseRound)),
+            )
+            .then_ignore(just(Token::While))
+            .then(expression())
+            .then(body)
+            .map(|((carried, condition), body)| Statement::Loop {
+                carried,
+                condition,
+                body,
+            });
+
+        if_statement
+            .or(loop_statement)
+            .or(assignment().map(Statement::Assign))
+    })
+}
+
+fn function_definition() -> impl Parser<Token, Ast, Error = ParseError> {
+    // inline fn TRANSFER
+    let macro_def = inlining().then_ignore(just(Token::Fn)).then(ident());
 
     // <arg1, arg2, ...>
     let macro_args = ident()
@@ -205,10 +328,14 @@ fn function_definition() -> impl Parser<Token, Ast, Error = Simple<Token>> {
         .then(reads_writes)
         .then(body)
         .map(
-            |(((((ident, macro_args), maybe_inputs), maybe_outputs), (reads, writes)), body)| {
+            |(
+                (((((inlining, ident), macro_args), maybe_inputs), maybe_outputs), (reads, writes)),
+                body,
+            )| {
                 let inputs = maybe_inputs?;
                 let outputs = maybe_outputs?;
                 Ok::<Ast, ()>(Ast::Function(Function {
+                    inlining,
                     ident,
                     macro_args,
                     inputs,
@@ -222,7 +349,7 @@ fn function_definition() -> impl Parser<Token, Ast, Error = Simple<Token>> {
         .map(|maybe_ast: Result<Ast, _>| maybe_ast.unwrap_or(Ast::Error))
 }
 
-fn extern_huff_macro_definition() -> impl Parser<Token, Ast, Error = Simple<Token>> {
+fn extern_huff_macro_definition() -> impl Parser<Token, Ast, Error = ParseError> {
     just(Token::External)
         .ignore_then(ident())
         .then(recover_for_round_delimited(
@@ -231,27 +358,43 @@ fn extern_huff_macro_definition() -> impl Parser<Token, Ast, Error = Simple<Toke
         .then(interactions())
         .map(|((ident, maybe_macro_args), interactions)| {
             let macro_args = maybe_macro_args?;
-            let (stack_in, stack_out, reads, writes) = interactions?;
-            Ok(Ast::HuffMacro(HuffMacro {
-                ident,
-                macro_args,
-                stack_in,
-                stack_out,
-                reads,
-                writes,
-            }))
+            let (stack_io, reads, writes) = interactions?;
+            Ok(match stack_io {
+                Some((stack_in, stack_out)) => Ast::HuffMacro(HuffMacro {
+                    ident,
+                    macro_args,
+                    stack_in,
+                    stack_out,
+                    reads,
+                    writes,
+                }),
+                None => Ast::HuffMacroDeps(HuffMacroDeps {
+                    ident,
+                    macro_args,
+                    reads,
+                    writes,
+                }),
+            })
         })
         .map(|maybe_ast: Result<Ast, ()>| maybe_ast.unwrap_or(Ast::Error))
 }
 
-fn extern_const_definition() -> impl Parser<Token, Ast, Error = Simple<Token>> {
+fn huff_import() -> impl Parser<Token, Ast, Error = ParseError> {
+    just(Token::Import)
+        .ignore_then(just(Token::Ident("huff".into())))
+        .ignore_then(select! { Token::Str(path) => path }.map_with_span(Spanned::new))
+        .map(Ast::HuffImport)
+}
+
+fn extern_const_definition() -> impl Parser<Token, Ast, Error = ParseError> {
     just(Token::Const).ignore_then(ident()).map(Ast::Const)
 }
 
-pub fn parser() -> impl Parser<Token, Vec<Spanned<Ast>>, Error = Simple<Token>> {
+pub fn parser() -> impl Parser<Token, Vec<Spanned<Ast>>, Error = ParseError> {
     dependency_definition()
         .or(extern_huff_macro_definition())
         .or(extern_const_definition())
+        .or(huff_import())
         .or(function_definition())
         .map_with_span(Spanned::new)
         .repeated()
@@ -259,5 +402,6 @@ pub fn parser() -> impl Parser<Token, Vec<Spanned<Ast>>, Error = Simple<Token>>
 }
 
 pub fn parse_tokens(tokens: Vec<Token>) -> (Option<Vec<Spanned<Ast>>>, Vec<Simple<Token>>) {
-    parser().parse_recovery(tokens)
+    let (ast_nodes, errs) = parser().parse_recovery(tokens);
+    (ast_nodes, errs.into_iter().map(|err| *err.0).collect())
 }
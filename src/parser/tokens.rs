@@ -8,6 +8,14 @@ pub enum Token {
     Dependency,
     External,
     Const,
+    Inline,
+    NoInline,
+    Import,
+    // ====== Control Flow Keywords ======
+    If,
+    Else,
+    Loop,
+    While,
     // ========= Sub Keywords =========
     Stack,
     Reads,
@@ -15,6 +23,7 @@ pub enum Token {
     // ============ Atoms =============
     Ident(String),
     Number(BigUint),
+    Str(String),
     // =========== Symbols ============
     Arrow,
     OpenRound,
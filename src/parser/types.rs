@@ -35,6 +35,16 @@ impl<T: Debug + Clone> Spanned<T> {
     }
 }
 
-pub fn resolve_span_span<T: Clone + Debug>(span_span: &Span, spans: &Vec<Spanned<T>>) -> Span {
-    spans[span_span.start].span.start..spans[span_span.end - 1].span.end
+pub fn resolve_span_span<T: Clone + Debug>(span_span: &Span, spans: &[Spanned<T>]) -> Span {
+    // Spans past the last token (e.g. an unexpected end of input) point to the end of the input.
+    let end_of_input = spans.last().map_or(0, |last| last.span.end);
+    let start = spans
+        .get(span_span.start)
+        .map_or(end_of_input, |spanned| spanned.span.start);
+    let end = span_span
+        .end
+        .checked_sub(1)
+        .and_then(|last| spans.get(last))
+        .map_or(end_of_input, |spanned| spanned.span.end);
+    start..end.max(start)
 }
@@ -8,6 +8,9 @@ pub enum Action {
     Dedup(usize, usize),
     UndoEffect(CompNodeId),
     UndoComp(CompNodeId, usize, bool),
+    UndoMultiComp(CompNodeId),
+    /// Computes the value again for the copy at the given stack index rather than duplicating it.
+    Rematerialize(CompNodeId, usize),
 }
 
 pub fn get_actions<'a>(
@@ -35,6 +38,11 @@ pub fn get_actions<'a>(
                 }
             })
         })
+        .chain((deepest_idx..total_stack_el).filter_map(move |i| {
+            let id = machine.stack[i];
+            (info.nodes[id].rematerializable && machine.blocked_by[id].is_some_and(|b| b > 0))
+                .then_some(Action::Rematerialize(id, i))
+        }))
         .chain(unpoppable.clone().into_iter().map(Action::Unpop))
         .chain(
             (0..info.nodes.len())
@@ -42,7 +50,21 @@ pub fn get_actions<'a>(
                     if machine.blocked_by[id] != Some(0) || unpoppable.contains(&id) {
                         return None;
                     }
-                    if info.nodes[id].produces_value {
+                    let node = &info.nodes[id];
+                    // Projections are only ever undone together via their multi-output node.
+                    if node.projection_of.is_some() {
+                        return None;
+                    }
+                    if !node.projections.is_empty() {
+                        let all_in_reach = node.projections.iter().all(|proj_id| {
+                            machine.blocked_by[*proj_id] == Some(0)
+                                && machine.stack.iter().index_of(proj_id).is_some_and(|idx| {
+                                    idx >= machine.stack.len().saturating_sub(17)
+                                })
+                        });
+                        return all_in_reach.then(|| vec![Action::UndoMultiComp(id)]);
+                    }
+                    if node.produces_value {
                         let idx = machine.stack.iter().index_of(&id).unwrap_or_else(|| {
                             panic!(
                         "Not-yet-done, comp node with 0 blocks not on stack (id: {}, stack: {:?})",
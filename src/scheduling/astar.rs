@@ -1,29 +1,71 @@
 use super::actions::get_actions;
+use crate::scheduling::cost::{steps_cost, CostModel};
+use crate::scheduling::error::{check_max_stack_depth, ScheduleError};
+use crate::scheduling::explored::SearchArena;
 use crate::scheduling::ir::IRGraph;
+use crate::scheduling::schedulers::swap_lower_bound;
+use crate::scheduling::step::validate_steps;
+use crate::scheduling::window::WindowSavings;
 use crate::scheduling::{BackwardsMachine, ScheduleInfo, Step};
 use crate::CommaSeparatable;
 use crate::TimeDelta;
-use std::collections::{BinaryHeap, HashMap};
-use std::hash::{BuildHasherDefault, Hash, Hasher};
-use std::time::Instant;
+use std::hash::{Hash, Hasher};
+use std::time::{Duration, Instant};
 
 #[derive(Debug, Clone)]
 pub struct SchedulingTracker {
-    start: Instant,
+    pub(crate) start: Instant,
     total_time: f64,
     final_cost: u32,
-    total_explored: usize,
-    total_collisions: usize,
+    /// Cost no schedule can go below.
+    lower_bound: u32,
+    cost_unit: String,
+    pub(crate) budget_exhausted: bool,
+    pub(crate) total_explored: usize,
+    /// Times an explored state was reached again at a lower cost.
+    pub(crate) total_improved: usize,
     capacity_estimation: (usize, usize),
+    /// Name and final cost of another scheduler run on the same block.
+    comparison: Option<(String, u32)>,
+    window_savings: Option<WindowSavings>,
 }
 
 impl SchedulingTracker {
-    pub fn record_end(&mut self, final_cost: u32, capacity_estimate: usize, final_capacity: usize) {
+    pub fn record_end(
+        &mut self,
+        final_cost: u32,
+        lower_bound: u32,
+        cost_unit: String,
+        capacity_estimate: usize,
+        final_capacity: usize,
+    ) {
         self.total_time = self.start.elapsed().as_secs_f64();
         self.final_cost = final_cost;
+        self.lower_bound = lower_bound.min(final_cost);
+        self.cost_unit = cost_unit;
         self.capacity_estimation = (capacity_estimate, final_capacity);
     }
 
+    /// Records the final cost of another scheduler on the same block to be reported alongside.
+    pub fn record_comparison(&mut self, scheduler: &str, final_cost: u32) {
+        self.comparison = Some((scheduler.to_string(), final_cost));
+    }
+
+    /// Records the improvement of re-optimizing the schedule after the search.
+    pub fn record_window_savings(&mut self, savings: WindowSavings) {
+        self.final_cost = self.final_cost.saturating_sub(savings.cost);
+        self.lower_bound = self.lower_bound.min(self.final_cost);
+        self.window_savings = Some(savings);
+    }
+
+    pub fn final_cost(&self) -> u32 {
+        self.final_cost
+    }
+
+    pub fn proven_optimal(&self) -> bool {
+        self.lower_bound == self.final_cost
+    }
+
     pub fn report(&self, indent: usize) {
         let indent = " ".repeat(indent);
         println!(
@@ -37,7 +79,38 @@ impl SchedulingTracker {
             self.total_explored.comma_sep(),
             ((self.total_explored as f64 / self.total_time).round() as usize).comma_sep()
         );
-        println!("{}cost (total SWAPs): {}", indent, self.final_cost);
+        println!("{}cost ({}): {}", indent, self.cost_unit, self.final_cost);
+        if let Some(savings) = &self.window_savings {
+            println!(
+                "{}Window re-optimization saved: {} swaps ({} {})",
+                indent, savings.swaps, savings.cost, self.cost_unit
+            );
+        }
+        if let Some((scheduler, cost)) = &self.comparison {
+            println!(
+                "{}{} cost: {} (difference: {:+})",
+                indent,
+                scheduler,
+                cost,
+                self.final_cost as i64 - *cost as i64
+            );
+        }
+        if self.budget_exhausted {
+            println!(
+                "{}Search budget exhausted, using best schedule found",
+                indent
+            );
+        }
+        if self.proven_optimal() {
+            println!("{}optimal: proven", indent);
+        } else {
+            println!(
+                "{}optimal: not proven (lower bound: {}, gap: {})",
+                indent,
+                self.lower_bound,
+                self.final_cost - self.lower_bound
+            );
+        }
         let (capacity_estimate, final_capacity) = self.capacity_estimation;
         if capacity_estimate == 0 {
             println!(
@@ -67,10 +140,10 @@ impl SchedulingTracker {
             }
         }
         println!(
-            "{}Overwritten explored: {} ({:.2}%)",
+            "{}Reached more cheaply: {} ({:.2}%)",
             indent,
-            self.total_collisions.comma_sep(),
-            self.total_collisions as f32 / final_capacity as f32 * 100.0
+            self.total_improved.comma_sep(),
+            self.total_improved as f32 / final_capacity as f32 * 100.0
         );
     }
 }
@@ -81,16 +154,22 @@ impl Default for SchedulingTracker {
             start: Instant::now(),
             total_time: 0.0,
             final_cost: 0,
+            lower_bound: 0,
+            cost_unit: String::new(),
+            budget_exhausted: false,
             total_explored: 0,
-            total_collisions: 0,
+            total_improved: 0,
             capacity_estimation: (0, 0),
+            comparison: None,
+            window_savings: None,
         }
     }
 }
 
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub struct ScheduleNode {
-    pub state: BackwardsMachine,
+    /// Index of the node's state among the explored states.
+    pub index: usize,
     /// Real, known cost.
     pub cost: u32,
     /// Total cost (including heuristic).
@@ -121,24 +200,14 @@ impl PartialOrd for ScheduleNode {
     }
 }
 
-#[derive(Clone)]
-pub struct Explored {
-    came_from: u64,
-    steps: Vec<Step>,
-    cost: u32,
-}
-
-type ExploredMap = HashMap<u64, Explored, BuildHasherDefault<NoopHasher>>;
-type ScheduleQueue = BinaryHeap<ScheduleNode>;
-
-fn hash_one_off<T: Hash>(value: &T) -> u64 {
+pub(crate) fn hash_one_off<T: Hash>(value: &T) -> u64 {
     let mut hashooor = ahash::AHasher::default();
     value.hash(&mut hashooor);
     hashooor.finish()
 }
 
 #[derive(Clone, Debug, Default)]
-struct NoopHasher(u64);
+pub(crate) struct NoopHasher(u64);
 
 impl Hasher for NoopHasher {
     fn write(&mut self, _bytes: &[u8]) {
@@ -154,27 +223,161 @@ impl Hasher for NoopHasher {
     }
 }
 
+/// Limits on how long a search may run. Once exhausted the best schedule found so far is returned,
+/// the search starting off with a greedily found one.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SearchBudget {
+    pub timeout: Option<Duration>,
+    pub max_explored: Option<usize>,
+}
+
+impl SearchBudget {
+    pub fn is_limited(&self) -> bool {
+        self.timeout.is_some() || self.max_explored.is_some()
+    }
+
+    pub(crate) fn exhausted(&self, tracker: &SchedulingTracker) -> bool {
+        self.exceeded(tracker.start, tracker.total_explored)
+    }
+
+    pub(crate) fn exceeded(&self, start: Instant, total_explored: usize) -> bool {
+        self.timeout
+            .is_some_and(|timeout| start.elapsed() >= timeout)
+            || self
+                .max_explored
+                .is_some_and(|max_explored| total_explored >= max_explored)
+    }
+}
+
+/// A complete schedule found before the search finished.
+pub(crate) struct Incumbent {
+    pub steps: Vec<Step>,
+    pub cost: u32,
+}
+
+/// Panics if `steps` aren't a valid schedule of the graph, only checked in debug builds to catch
+/// incorrectly reconstructed paths.
+pub(crate) fn debug_validate(graph: &IRGraph, steps: &[Step]) {
+    if cfg!(debug_assertions) {
+        if let Err(err) = validate_steps(graph, steps) {
+            panic!("Invalid schedule: {}", err);
+        }
+    }
+}
+
+/// Steps bringing an end state's stack into the target layout and their total cost. `apply` already
+/// does so for states finished by an action, leaving only start states that are done to begin with.
+pub(crate) fn final_swaps(
+    info: ScheduleInfo,
+    state: &BackwardsMachine,
+) -> Result<(Vec<Step>, u32), ScheduleError> {
+    let mut steps = vec![];
+    state.clone().swap_to_target(info, &mut steps)?;
+    steps.reverse();
+    let cost = steps_cost(info.cost_model, &steps);
+    Ok((steps, cost))
+}
+
+/// Repeatedly takes the action with the lowest estimated total cost, without ever backtracking.
+/// Fails if it runs into a dead end (e.g. due to the max stack depth).
+pub(crate) fn greedy_schedule<S: AStarScheduler>(
+    scheduler: &S,
+    info: ScheduleInfo,
+    start: &BackwardsMachine,
+    max_stack_depth: usize,
+) -> Option<Incumbent> {
+    let mut state = start.clone();
+    let mut cost = 0u32;
+    let mut all_steps = vec![];
+    let mut at_end = state.all_done();
+    while !at_end {
+        let (new_cost, new_at_end, new_state, steps) = get_actions(info, &state)
+            .filter_map(|action| {
+                let mut new_state = state.clone();
+                let mut steps = vec![];
+                let at_end = new_state.apply(info, action, &mut steps).ok()?;
+                if new_state.stack.len() > max_stack_depth {
+                    return None;
+                }
+                let new_cost = cost.saturating_add(steps_cost(info.cost_model, &steps));
+                Some((new_cost, at_end, new_state, steps))
+            })
+            .min_by_key(|(new_cost, at_end, new_state, _)| {
+                (
+                    new_cost.saturating_add(
+                        scheduler.estimate_remaining_cost(info, new_state, *new_cost),
+                    ),
+                    !at_end,
+                )
+            })?;
+        cost = new_cost;
+        at_end = new_at_end;
+        state = new_state;
+        all_steps.extend(steps);
+    }
+    all_steps.reverse();
+    let (swaps, swap_cost) = final_swaps(info, &state).ok()?;
+    all_steps.extend(swaps);
+    Some(Incumbent {
+        steps: all_steps,
+        cost: cost.saturating_add(swap_cost),
+    })
+}
+
 pub trait AStarScheduler: Sized + Sync + Send {
     fn schedule(
+        self,
+        graph: &IRGraph,
+        cost_model: &dyn CostModel,
+        max_stack_depth: usize,
+        budget: SearchBudget,
+    ) -> Result<(Vec<Step>, SchedulingTracker), ScheduleError> {
+        self.schedule_in(
+            &mut SearchArena::new(),
+            graph,
+            cost_model,
+            max_stack_depth,
+            budget,
+        )
+    }
+
+    /// Schedules like `schedule`, keeping the search's states in `arena` which is reset first. Reusing
+    /// one arena avoids allocating the memory for every search anew.
+    fn schedule_in(
         mut self,
+        arena: &mut SearchArena,
         graph: &IRGraph,
+        cost_model: &dyn CostModel,
         max_stack_depth: usize,
-    ) -> (Vec<Step>, SchedulingTracker) {
+        budget: SearchBudget,
+    ) -> Result<(Vec<Step>, SchedulingTracker), ScheduleError> {
+        check_max_stack_depth(max_stack_depth)?;
         let mut tracker = SchedulingTracker::default();
 
-        let info = ScheduleInfo::from(graph);
+        let info = ScheduleInfo::new(graph, cost_model);
         let start = BackwardsMachine::new(
             graph.output_ids.iter().rev().cloned().collect(),
             graph.nodes.iter().map(|node| node.blocked_by).collect(),
         );
+        let start_bound = self.lower_bound(info, &start);
+        let mut incumbent = budget
+            .is_limited()
+            .then(|| greedy_schedule(&self, info, &start, max_stack_depth))
+            .flatten();
+
         let est_capacity = self.estimate_explored_map_size(info, &start, max_stack_depth);
-        let mut queue: ScheduleQueue = BinaryHeap::with_capacity(est_capacity);
-        let mut explored: ExploredMap =
-            HashMap::with_capacity_and_hasher(est_capacity, Default::default());
+        arena.reset();
+        arena.reserve(est_capacity);
+        let SearchArena { explored, queue } = arena;
+        let (start_index, _) = explored.reach(&start, None, &[], 0).unwrap();
+
+        // Last reason a state couldn't be continued from, reported if no schedule is found.
+        let mut dead_end = None;
+        let mut steps = Vec::with_capacity(30);
 
         let score = self.estimate_remaining_cost(info, &start, 0);
         queue.push(ScheduleNode {
-            state: start.clone(),
+            index: start_index,
             cost: 0,
             score,
             at_end: start.all_done(),
@@ -182,74 +385,121 @@ pub trait AStarScheduler: Sized + Sync + Send {
 
         // 1. Pop top of priority queue (node closest to end according to actual cost + estimated
         //    remaining distance).
-        while let Some(mut node) = queue.pop() {
-            let came_from = hash_one_off(&node.state);
+        while let Some(node) = queue.pop() {
+            // Skip nodes whose state was reached more cheaply after they were queued.
+            if explored.cost(node.index) < node.cost {
+                continue;
+            }
+            let came_from = node.index;
             // 2a. If the shortest node is the end we know we found our solution, accumulate the
             // steps and return.
-            if node.at_end {
-                let mut state_key = came_from;
-                let mut all_steps = vec![];
-                while let Some(e) = explored.remove(&state_key) {
-                    all_steps.extend(e.steps.into_iter().rev());
-                    state_key = e.came_from;
-                }
+            if node.at_end && incumbent.as_ref().is_none_or(|inc| node.cost < inc.cost) {
+                let mut all_steps = explored.trace_steps(came_from);
+                let (swaps, swap_cost) = final_swaps(info, &explored.state(came_from))?;
+                all_steps.extend(swaps);
+                debug_validate(graph, &all_steps);
 
-                let mut final_swaps = vec![];
-                node.state.swap_to_target(info, &mut final_swaps).unwrap();
-                final_swaps.reverse();
-                all_steps.extend(final_swaps);
-
-                let explored_size = explored.len();
+                let cost = node.cost.saturating_add(swap_cost);
+                let lower_bound = if self.admissible() { cost } else { start_bound };
+                tracker.record_end(
+                    cost.saturating_add(cost_model.fixed_cost()),
+                    lower_bound.saturating_add(cost_model.fixed_cost()),
+                    cost_model.unit(),
+                    est_capacity,
+                    explored.len(),
+                );
+                return Ok((all_steps, tracker));
+            }
 
-                // TODO: Use arena allocator to be able to more efficiently drop the allocations.
-                // Degen: Purposefully leak the data structures as it takes *a lot* of time to
-                // properly drop and clear.
-                std::mem::forget(explored);
-                std::mem::forget(queue);
+            // 2b. Out of budget or no node can lead to anything cheaper than what was already
+            // found, settle for the best schedule so far.
+            let out_of_budget = budget.exhausted(&tracker);
+            let settled = incumbent
+                .as_ref()
+                .is_some_and(|inc| node.at_end || (self.admissible() && node.score >= inc.cost));
+            if out_of_budget || settled {
+                let Incumbent { steps, cost } = incumbent.ok_or(ScheduleError::BudgetExhausted)?;
+                debug_validate(graph, &steps);
+                let lower_bound = if self.admissible() {
+                    node.score.max(start_bound)
+                } else {
+                    start_bound
+                };
+                tracker.budget_exhausted = !settled;
+                tracker.record_end(
+                    cost.saturating_add(cost_model.fixed_cost()),
+                    lower_bound.saturating_add(cost_model.fixed_cost()),
+                    cost_model.unit(),
+                    est_capacity,
+                    explored.len(),
+                );
+                return Ok((steps, tracker));
+            }
 
-                tracker.record_end(node.cost, est_capacity, explored_size);
-                return (all_steps, tracker);
+            if incumbent.as_ref().is_some_and(|inc| node.cost >= inc.cost) {
+                continue;
             }
 
-            // 2b. Not at the end so we explore all possible neighbours.
+            // 2c. Not at the end so we explore all possible neighbours.
             //
-            queue.extend(get_actions(info, &node.state).filter_map(|action| {
-                let mut new_state = node.state.clone();
-                let mut steps = Vec::with_capacity(30);
-                let at_end = new_state.apply(info, action, &mut steps).unwrap();
+            let state = explored.state(came_from);
+            queue.extend(get_actions(info, &state).filter_map(|action| {
+                let mut new_state = state.clone();
+                steps.clear();
+                let at_end = match new_state.apply(info, action, &mut steps) {
+                    Ok(at_end) => at_end,
+                    Err(err) => {
+                        dead_end = Some(err);
+                        return None;
+                    }
+                };
                 if new_state.stack.len() > max_stack_depth {
                     return None;
                 }
-                let new_cost = node.cost + steps.iter().map(|step| step.cost()).sum::<u32>();
+                let new_cost = node.cost.saturating_add(steps_cost(cost_model, &steps));
                 tracker.total_explored += 1;
-                let new_state_hash = hash_one_off(&new_state);
 
-                match explored.get(&new_state_hash) {
-                    Some(e) => new_cost < e.cost,
-                    None => true,
-                }
-                .then(|| {
-                    let out = explored.insert(
-                        new_state_hash,
-                        Explored {
-                            came_from,
-                            cost: new_cost,
-                            steps,
-                        },
-                    );
-                    tracker.total_collisions += if out.is_some() { 1 } else { 0 };
-                    let score = new_cost + self.estimate_remaining_cost(info, &new_state, new_cost);
-                    ScheduleNode {
-                        state: new_state,
+                let (index, improved) =
+                    explored.reach(&new_state, Some(came_from), &steps, new_cost)?;
+                tracker.total_improved += if improved { 1 } else { 0 };
+                // Remember complete schedules in case the search has to stop early.
+                if at_end
+                    && budget.is_limited()
+                    && incumbent.as_ref().is_none_or(|inc| new_cost < inc.cost)
+                {
+                    let steps = explored.trace_steps(index);
+                    debug_validate(graph, &steps);
+                    incumbent = Some(Incumbent {
+                        steps,
                         cost: new_cost,
-                        score,
-                        at_end,
-                    }
+                    });
+                }
+                let score = new_cost
+                    .saturating_add(self.estimate_remaining_cost(info, &new_state, new_cost));
+                Some(ScheduleNode {
+                    index,
+                    cost: new_cost,
+                    score,
+                    at_end,
                 })
             }));
         }
 
-        panic!("TODO: Impossible to schedule within specified bounds (likely stack-too-deep).")
+        match incumbent {
+            // States are only left out for costing at least as much as the incumbent, having
+            // explored all others proves it optimal.
+            Some(Incumbent { steps, cost }) => {
+                tracker.record_end(
+                    cost.saturating_add(cost_model.fixed_cost()),
+                    cost.saturating_add(cost_model.fixed_cost()),
+                    cost_model.unit(),
+                    est_capacity,
+                    explored.len(),
+                );
+                Ok((steps, tracker))
+            }
+            None => Err(dead_end.unwrap_or(ScheduleError::StackTooDeep(max_stack_depth))),
+        }
     }
 
     fn estimate_explored_map_size(
@@ -268,4 +518,70 @@ pub trait AStarScheduler: Sized + Sync + Send {
         _state: &BackwardsMachine,
         _cost: u32,
     ) -> u32;
+
+    /// Whether `estimate_remaining_cost` never overestimates the remaining cost, proving the first
+    /// schedule found to be optimal.
+    fn admissible(&self) -> bool {
+        false
+    }
+
+    /// Cost that no schedule starting from `state` can go below.
+    fn lower_bound(&self, info: ScheduleInfo, state: &BackwardsMachine) -> u32 {
+        let estimate = if self.admissible() {
+            self.estimate_remaining_cost(info, state, 0)
+        } else {
+            0
+        };
+        estimate.max(
+            swap_lower_bound(info, state).saturating_mul(info.cost_model.step_cost(&Step::Swap(1))),
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::scheduling::cost::SwapCount;
+    use crate::scheduling::schedulers::{Dijkstra, Guessooor};
+    use crate::test_utils::{function_blocks, PERMUTATION};
+
+    #[test]
+    fn test_start_state_swapped_to_target() {
+        let block = function_blocks(PERMUTATION, "F").remove(0);
+        let (steps, tracker) = Dijkstra
+            .schedule(&block.graph, &SwapCount, 1024, SearchBudget::default())
+            .unwrap();
+        assert_eq!(validate_steps(&block.graph, &steps), Ok(()));
+        assert_eq!(steps.len(), 2);
+        assert_eq!(tracker.final_cost(), 2);
+    }
+
+    #[test]
+    fn test_budget_exhausted_schedule() {
+        let block = function_blocks(PERMUTATION, "F").remove(0);
+        let budget = SearchBudget {
+            max_explored: Some(1),
+            ..Default::default()
+        };
+        let (steps, tracker) = Guessooor::new(0.035)
+            .schedule(&block.graph, &SwapCount, 1024, budget)
+            .unwrap();
+        assert_eq!(validate_steps(&block.graph, &steps), Ok(()));
+        assert_eq!(tracker.final_cost(), 2);
+
+        // The incumbent the search falls back on must be complete on its own.
+        let info = ScheduleInfo::new(&block.graph, &SwapCount);
+        let start = BackwardsMachine::new(
+            block.graph.output_ids.iter().rev().cloned().collect(),
+            block
+                .graph
+                .nodes
+                .iter()
+                .map(|node| node.blocked_by)
+                .collect(),
+        );
+        let incumbent = greedy_schedule(&Guessooor::new(0.035), info, &start, 1024).unwrap();
+        assert_eq!(validate_steps(&block.graph, &incumbent.steps), Ok(()));
+        assert_eq!(incumbent.cost, 2);
+    }
 }
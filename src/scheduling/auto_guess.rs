@@ -0,0 +1,188 @@
+use crate::scheduling::astar::{SchedulingTracker, SearchBudget};
+use crate::scheduling::Step;
+use std::time::{Duration, Instant};
+
+/// `Guessooor` factor used unless another one is given or tuned.
+pub const DEFAULT_GUESSOR_FACTOR: f32 = 0.035;
+
+/// Factors tried in order, from quick and greedy to slow and close to `Dijkstra`.
+pub const AUTO_GUESS_FACTORS: [f32; 9] = [1.0, 0.5, 0.25, 0.12, 0.07, 0.035, 0.02, 0.01, 0.005];
+
+/// Consecutive factors that have to result in no improvement for the results to be considered
+/// stable.
+const STABLE_ROUNDS: usize = 2;
+
+/// Schedules of all blocks of a function.
+pub type Schedules = Vec<(Vec<Step>, SchedulingTracker)>;
+
+/// Outcome of tuning the `Guessooor` factor.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TunedGuess {
+    pub factor: f32,
+    /// Amount of factors that were tried.
+    pub tried: usize,
+}
+
+fn total_cost(schedules: &Schedules) -> u32 {
+    schedules.iter().fold(0u32, |total, (_, tracker)| {
+        total.saturating_add(tracker.final_cost())
+    })
+}
+
+/// Calls `schedule` with the `AUTO_GUESS_FACTORS` in descending order, keeping the cheapest
+/// schedules. Stops once the results have stabilized, are proven to be optimal or `time_budget` has
+/// run out, the budget passed to `schedule` being limited to the time that's left. The
+/// `DEFAULT_GUESSOR_FACTOR` is tried regardless so that tuning never does worse than the default.
+/// Only returns an error if no factor resulted in a schedule.
+pub fn tune_guess<E>(
+    time_budget: Duration,
+    budget: SearchBudget,
+    mut schedule: impl FnMut(f32, SearchBudget) -> Result<Schedules, E>,
+) -> Result<(Schedules, TunedGuess), E> {
+    let deadline = Instant::now() + time_budget;
+    let mut best: Option<(Schedules, TunedGuess)> = None;
+    let mut last_err = None;
+    let mut rounds_without_improvement = 0;
+    let mut tried = 0;
+    let mut tried_default = false;
+
+    for factor in AUTO_GUESS_FACTORS {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        // The first factor is always tried so that there's a schedule to return.
+        if tried > 0 && (remaining.is_zero() || rounds_without_improvement >= STABLE_ROUNDS) {
+            if tried_default {
+                break;
+            }
+            if factor != DEFAULT_GUESSOR_FACTOR {
+                continue;
+            }
+        }
+        tried += 1;
+        tried_default |= factor == DEFAULT_GUESSOR_FACTOR;
+        let budget = SearchBudget {
+            timeout: Some(
+                budget
+                    .timeout
+                    .map_or(remaining, |timeout| timeout.min(remaining)),
+            ),
+            ..budget
+        };
+        let schedules = match schedule(factor, budget) {
+            Ok(schedules) => schedules,
+            Err(err) => {
+                last_err = Some(err);
+                rounds_without_improvement += 1;
+                continue;
+            }
+        };
+        let tuned = TunedGuess { factor, tried };
+        let proven_optimal = schedules
+            .iter()
+            .all(|(_, tracker)| tracker.proven_optimal());
+        match best.as_mut() {
+            Some((best_schedules, best_tuned)) => {
+                best_tuned.tried = tuned.tried;
+                if total_cost(&schedules) < total_cost(best_schedules) {
+                    *best_schedules = schedules;
+                    best_tuned.factor = factor;
+                    rounds_without_improvement = 0;
+                } else {
+                    rounds_without_improvement += 1;
+                }
+            }
+            None => best = Some((schedules, tuned)),
+        }
+        if proven_optimal {
+            break;
+        }
+    }
+
+    match (best, last_err) {
+        (Some(best), _) => Ok(best),
+        (None, Some(err)) => Err(err),
+        (None, None) => unreachable!("First factor is always tried"),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::scheduling::astar::AStarScheduler;
+    use crate::scheduling::cost::SwapCount;
+    use crate::scheduling::error::ScheduleError;
+    use crate::scheduling::explored::SearchArena;
+    use crate::scheduling::schedulers::Guessooor;
+    use crate::test_utils::get_blocks;
+    use crate::transformer::ir_gen::IRBlock;
+
+    fn default_cost(blocks: &[IRBlock]) -> u32 {
+        blocks
+            .iter()
+            .map(|block| {
+                let (_, tracker) = Guessooor::new(DEFAULT_GUESSOR_FACTOR)
+                    .schedule(&block.graph, &SwapCount, 1024, SearchBudget::default())
+                    .unwrap();
+                tracker.final_cost()
+            })
+            .sum()
+    }
+
+    #[test]
+    fn test_tuned_guess_beats_greediest() {
+        let blocks = get_blocks(include_str!("../../examples/transfer_ma.balls"));
+        let mut arena = SearchArena::new();
+        let mut costs = vec![];
+        let (schedules, tuned) = tune_guess(
+            Duration::from_secs(60),
+            SearchBudget::default(),
+            |factor, budget| {
+                let schedules: Schedules = blocks
+                    .iter()
+                    .map(|block| {
+                        Guessooor::new(factor).schedule_in(
+                            &mut arena,
+                            &block.graph,
+                            &SwapCount,
+                            1024,
+                            budget,
+                        )
+                    })
+                    .collect::<Result<_, ScheduleError>>()?;
+                costs.push((factor, total_cost(&schedules)));
+                Ok::<_, ScheduleError>(schedules)
+            },
+        )
+        .unwrap();
+        let cost = total_cost(&schedules);
+        assert_eq!(tuned.tried, costs.len());
+        assert_eq!(cost, costs.iter().map(|(_, cost)| *cost).min().unwrap());
+        assert!(cost <= default_cost(&blocks));
+        // Factors may have been skipped, the tried ones are looked up by value.
+        let tuned_cost = costs
+            .iter()
+            .find(|(factor, _)| *factor == tuned.factor)
+            .map(|(_, cost)| *cost);
+        assert_eq!(tuned_cost, Some(cost));
+    }
+
+    #[test]
+    fn test_default_factor_always_tried() {
+        // Stable long before the default factor, which is the only one doing any better.
+        let schedule = |factor, _| {
+            let mut tracker = SchedulingTracker::default();
+            let cost = if factor == DEFAULT_GUESSOR_FACTOR {
+                1
+            } else {
+                2
+            };
+            tracker.record_end(cost, 0, String::new(), 0, 0);
+            Ok::<Schedules, ()>(vec![(vec![], tracker)])
+        };
+        let (schedules, tuned) =
+            tune_guess(Duration::from_secs(60), SearchBudget::default(), schedule).unwrap();
+        assert_eq!(total_cost(&schedules), 1);
+        assert_eq!(tuned.factor, DEFAULT_GUESSOR_FACTOR);
+        // Stable after 1.0, 0.5 and 0.25, tuning goes on after the default's improvement.
+        assert_eq!(tuned.tried, 6);
+    }
+}
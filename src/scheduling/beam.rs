@@ -0,0 +1,201 @@
+use super::actions::get_actions;
+use crate::scheduling::astar::{
+    debug_validate, final_swaps, AStarScheduler, ScheduleNode, SchedulingTracker, SearchBudget,
+};
+use crate::scheduling::cost::{steps_cost, CostModel};
+use crate::scheduling::error::{check_max_stack_depth, ScheduleError};
+use crate::scheduling::explored::SearchArena;
+use crate::scheduling::ir::IRGraph;
+use crate::scheduling::{BackwardsMachine, ScheduleInfo, Step};
+
+/// Beam search, explores the states one action at a time keeping only the `width` states with the
+/// best score (according to the wrapped scheduler) after every action. Every action brings the
+/// machine closer to its end so the amount of rounds is bounded by the size of the graph, making
+/// the runtime linear in it. Results are not guaranteed to be optimal.
+#[derive(Debug, Clone)]
+pub struct BeamScheduler<S: AStarScheduler> {
+    heuristic: S,
+    width: usize,
+}
+
+impl<S: AStarScheduler> BeamScheduler<S> {
+    pub fn new(heuristic: S, width: usize) -> Self {
+        Self {
+            heuristic,
+            width: width.max(1),
+        }
+    }
+}
+
+impl<S: AStarScheduler> AStarScheduler for BeamScheduler<S> {
+    fn schedule_in(
+        self,
+        arena: &mut SearchArena,
+        graph: &IRGraph,
+        cost_model: &dyn CostModel,
+        max_stack_depth: usize,
+        budget: SearchBudget,
+    ) -> Result<(Vec<Step>, SchedulingTracker), ScheduleError> {
+        check_max_stack_depth(max_stack_depth)?;
+        let mut tracker = SchedulingTracker::default();
+
+        let info = ScheduleInfo::new(graph, cost_model);
+        let start = BackwardsMachine::new(
+            graph.output_ids.iter().rev().cloned().collect(),
+            graph.nodes.iter().map(|node| node.blocked_by).collect(),
+        );
+        let start_bound = self.lower_bound(info, &start);
+
+        arena.reset();
+        let explored = &mut arena.explored;
+        let (start_index, _) = explored.reach(&start, None, &[], 0).unwrap();
+        let mut beam = vec![ScheduleNode {
+            index: start_index,
+            cost: 0,
+            score: self.estimate_remaining_cost(info, &start, 0),
+            at_end: start.all_done(),
+        }];
+        // Cheapest end state reached so far.
+        let mut best_end: Option<ScheduleNode> = None;
+        let mut dead_end = None;
+        let mut steps = Vec::with_capacity(30);
+
+        while !beam.is_empty() {
+            let mut next_beam = vec![];
+            for node in beam {
+                if node.at_end {
+                    if best_end.as_ref().is_none_or(|best| node.cost < best.cost) {
+                        best_end = Some(node);
+                    }
+                    continue;
+                }
+                let state = explored.state(node.index);
+                for action in get_actions(info, &state) {
+                    let mut new_state = state.clone();
+                    steps.clear();
+                    let at_end = match new_state.apply(info, action, &mut steps) {
+                        Ok(at_end) => at_end,
+                        Err(err) => {
+                            dead_end = Some(err);
+                            continue;
+                        }
+                    };
+                    if new_state.stack.len() > max_stack_depth {
+                        continue;
+                    }
+                    let new_cost = node.cost.saturating_add(steps_cost(cost_model, &steps));
+                    tracker.total_explored += 1;
+                    // States already reached at no higher cost are still candidates as they may
+                    // not have been kept before, continuing from their cheapest known path.
+                    let (index, improved) =
+                        explored.reach_or_find(&new_state, Some(node.index), &steps, new_cost);
+                    tracker.total_improved += if improved == Some(true) { 1 } else { 0 };
+                    let cost = explored.cost(index);
+                    next_beam.push(ScheduleNode {
+                        index,
+                        cost,
+                        score: cost
+                            .saturating_add(self.estimate_remaining_cost(info, &new_state, cost)),
+                        at_end,
+                    });
+                }
+            }
+
+            // Keep the best states, leaving out duplicates, ones reached more cheaply later in the
+            // round and ones that can't beat the cheapest schedule found.
+            next_beam.retain(|node| {
+                explored.cost(node.index) == node.cost
+                    && best_end.as_ref().is_none_or(|best| node.score < best.cost)
+            });
+            next_beam.sort_unstable_by_key(|node| node.index);
+            next_beam.dedup_by_key(|node| node.index);
+            next_beam.sort_unstable_by(|a, b| b.cmp(a));
+            // Once out of budget only the best state is followed, finishing as quickly as possible.
+            let width = if budget.exhausted(&tracker) {
+                tracker.budget_exhausted = true;
+                1
+            } else {
+                self.width
+            };
+            next_beam.truncate(width);
+            beam = next_beam;
+        }
+
+        let best_end =
+            best_end.ok_or(dead_end.unwrap_or(ScheduleError::StackTooDeep(max_stack_depth)))?;
+        let mut all_steps = explored.trace_steps(best_end.index);
+        let (swaps, swap_cost) = final_swaps(info, &explored.state(best_end.index))?;
+        all_steps.extend(swaps);
+        debug_validate(graph, &all_steps);
+
+        tracker.record_end(
+            best_end
+                .cost
+                .saturating_add(swap_cost)
+                .saturating_add(cost_model.fixed_cost()),
+            start_bound.saturating_add(cost_model.fixed_cost()),
+            cost_model.unit(),
+            0,
+            explored.len(),
+        );
+        Ok((all_steps, tracker))
+    }
+
+    fn estimate_remaining_cost(
+        &self,
+        info: ScheduleInfo,
+        state: &BackwardsMachine,
+        cost: u32,
+    ) -> u32 {
+        self.heuristic.estimate_remaining_cost(info, state, cost)
+    }
+
+    fn admissible(&self) -> bool {
+        self.heuristic.admissible()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::scheduling::cost::{steps_cost, SwapCount};
+    use crate::scheduling::schedulers::{Dijkstra, Guessooor};
+    use crate::test_utils::{assert_never_beats_dijkstra, get_blocks, small_blocks, swap_schedule};
+
+    #[test]
+    fn test_beam_never_beats_optimal() {
+        for width in [1, 16] {
+            assert_never_beats_dijkstra(&small_blocks(), 1024, |graph, max_stack_depth| {
+                swap_schedule(
+                    BeamScheduler::new(Guessooor::new(0.035), width),
+                    graph,
+                    max_stack_depth,
+                )
+            });
+        }
+        for block in get_blocks(include_str!("../../examples/permit_ma.balls")) {
+            swap_schedule(
+                BeamScheduler::new(Guessooor::new(0.035), 16),
+                &block.graph,
+                1024,
+            );
+        }
+    }
+
+    #[test]
+    fn test_narrow_beam_loses_optimality() {
+        let block = get_blocks(include_str!("../../examples/Math.balls")).remove(0);
+        let cost = |width| {
+            let steps = swap_schedule(
+                BeamScheduler::new(Guessooor::new(0.035), width),
+                &block.graph,
+                1024,
+            );
+            steps_cost(&SwapCount, &steps)
+        };
+        let optimal = steps_cost(&SwapCount, &swap_schedule(Dijkstra, &block.graph, 1024));
+        // Keeping only the most promising state drops the path to the optimal schedule.
+        assert!(cost(1) > optimal);
+        assert_eq!(cost(16), optimal);
+    }
+}
@@ -0,0 +1,295 @@
+use crate::scheduling::ir::{CompNodeId, IRGraph};
+use crate::scheduling::Step;
+use std::fmt::Debug;
+
+/// Total cost of `steps`. Costs saturate rather than overflow throughout the search as weighted
+/// objectives can make them arbitrarily large.
+pub fn steps_cost(cost_model: &dyn CostModel, steps: &[Step]) -> u32 {
+    steps.iter().fold(0u32, |total, step| {
+        total.saturating_add(cost_model.step_cost(step))
+    })
+}
+
+/// The objective minimized by the schedulers, assigns every step its cost.
+pub trait CostModel: Debug + Sync + Send {
+    fn step_cost(&self, step: &Step) -> u32;
+
+    /// Cost incurred by every schedule regardless of ordering, left out of the search and only
+    /// added to the final cost.
+    fn fixed_cost(&self) -> u32 {
+        0
+    }
+
+    /// Describes what the cost is measured in, used when reporting.
+    fn unit(&self) -> String;
+}
+
+/// Minimizes the amount of swaps, stack manipulation that's never necessary in register based
+/// machines.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SwapCount;
+
+impl CostModel for SwapCount {
+    fn step_cost(&self, step: &Step) -> u32 {
+        match step {
+            Step::Swap(_) => 1,
+            Step::Pop => 0,
+            Step::Dup(_) => 0,
+            Step::Comp(_, _) => 0,
+        }
+    }
+
+    fn unit(&self) -> String {
+        "total SWAPs".into()
+    }
+}
+
+/// Charges every step a cost independent of the state it's taken in. Steps taken by every schedule
+/// are moved into the fixed cost, keeping them free lets the search focus on the states that
+/// actually differ:
+/// - nodes are computed exactly once unless they're rematerializable
+/// - values that are never used are popped exactly once
+/// - every copy of a value beyond the first is a dup, unless it could be rematerialized
+#[derive(Debug, Clone, Default)]
+struct StaticCost {
+    /// Cost of computing the node with the given ID.
+    comp: Vec<u32>,
+    swap: u32,
+    dup: u32,
+    fixed: u32,
+}
+
+impl StaticCost {
+    fn new(mut comp: Vec<u32>, graph: &IRGraph, swap: u32, dup: u32, pop: u32) -> Self {
+        let mut fixed = 0;
+        for (cost, node) in comp.iter_mut().zip(graph.nodes.iter()) {
+            if !node.rematerializable {
+                fixed += std::mem::take(cost);
+            }
+        }
+
+        let mut stack_counts = vec![0u32; graph.nodes.len()];
+        for id in graph
+            .nodes
+            .iter()
+            .flat_map(|node| node.operands.iter())
+            .chain(graph.output_ids.iter())
+        {
+            stack_counts[*id] += 1;
+        }
+        let total_pops = graph
+            .nodes
+            .iter()
+            .zip(stack_counts.iter())
+            .filter(|(node, count)| node.produces_value && **count == 0)
+            .count() as u32;
+        fixed += total_pops * pop;
+
+        let dup = if graph.nodes.iter().any(|node| node.rematerializable) {
+            dup
+        } else {
+            let total_dups: u32 = stack_counts.iter().map(|count| count.max(&1) - 1).sum();
+            fixed += total_dups * dup;
+            0
+        };
+
+        Self {
+            comp,
+            swap,
+            dup,
+            fixed,
+        }
+    }
+
+    fn step_cost(&self, step: &Step) -> u32 {
+        match step {
+            Step::Swap(_) => self.swap,
+            Step::Dup(_) => self.dup,
+            Step::Pop => 0,
+            Step::Comp(id, _) => self.comp_cost(*id),
+        }
+    }
+
+    fn comp_cost(&self, id: CompNodeId) -> u32 {
+        self.comp[id]
+    }
+}
+
+/// Minimizes the gas spent executing the schedule, computations being charged the static gas cost
+/// of their node.
+#[derive(Debug, Clone, Default)]
+pub struct GasCost(StaticCost);
+
+const DUP_SWAP_GAS: u32 = 3;
+const POP_GAS: u32 = 2;
+
+impl GasCost {
+    pub fn new(comp_gas: Vec<u32>, graph: &IRGraph) -> Self {
+        Self(StaticCost::new(
+            comp_gas,
+            graph,
+            DUP_SWAP_GAS,
+            DUP_SWAP_GAS,
+            POP_GAS,
+        ))
+    }
+}
+
+impl CostModel for GasCost {
+    fn step_cost(&self, step: &Step) -> u32 {
+        self.0.step_cost(step)
+    }
+
+    fn fixed_cost(&self) -> u32 {
+        self.0.fixed
+    }
+
+    fn unit(&self) -> String {
+        "gas".into()
+    }
+}
+
+/// Minimizes the size of the scheduled code, computations being charged the encoded length of
+/// their node.
+#[derive(Debug, Clone, Default)]
+pub struct BytecodeSize(StaticCost);
+
+/// Stack manipulating opcodes take no immediates.
+const STACK_OP_BYTES: u32 = 1;
+
+impl BytecodeSize {
+    pub fn new(comp_bytes: Vec<u32>, graph: &IRGraph) -> Self {
+        Self(StaticCost::new(
+            comp_bytes,
+            graph,
+            STACK_OP_BYTES,
+            STACK_OP_BYTES,
+            STACK_OP_BYTES,
+        ))
+    }
+}
+
+impl CostModel for BytecodeSize {
+    fn step_cost(&self, step: &Step) -> u32 {
+        self.0.step_cost(step)
+    }
+
+    fn fixed_cost(&self) -> u32 {
+        self.0.fixed
+    }
+
+    fn unit(&self) -> String {
+        "bytes".into()
+    }
+}
+
+/// Minimizes a weighted sum of other objectives. Large weights saturate rather than overflow,
+/// costs that large are equally unattractive anyway.
+#[derive(Debug)]
+pub struct Weighted {
+    terms: Vec<(u32, Box<dyn CostModel>)>,
+}
+
+impl Weighted {
+    pub fn new(terms: Vec<(u32, Box<dyn CostModel>)>) -> Self {
+        Self { terms }
+    }
+}
+
+impl Weighted {
+    fn weighted_sum(&self, cost: impl Fn(&dyn CostModel) -> u32) -> u32 {
+        self.terms.iter().fold(0u32, |total, (weight, model)| {
+            total.saturating_add(weight.saturating_mul(cost(model.as_ref())))
+        })
+    }
+}
+
+impl CostModel for Weighted {
+    fn step_cost(&self, step: &Step) -> u32 {
+        self.weighted_sum(|model| model.step_cost(step))
+    }
+
+    fn fixed_cost(&self) -> u32 {
+        self.weighted_sum(|model| model.fixed_cost())
+    }
+
+    fn unit(&self) -> String {
+        self.terms
+            .iter()
+            .map(|(weight, model)| format!("{} * {}", weight, model.unit()))
+            .collect::<Vec<_>>()
+            .join(" + ")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::scheduling::astar::{AStarScheduler, SearchBudget};
+    use crate::scheduling::beam::BeamScheduler;
+    use crate::scheduling::explored::SearchArena;
+    use crate::scheduling::ida::IterativeDeepening;
+    use crate::scheduling::parallel::HashDistributed;
+    use crate::scheduling::schedulers::{Guessooor, LowerBound};
+    use crate::scheduling::window::reoptimize_windows;
+    use crate::test_utils::{get_blocks, SMALL_EXAMPLES};
+    use crate::transformer::ir_gen::ValueSource;
+
+    #[test]
+    fn test_weighted_saturates() {
+        let weighted = Weighted::new(vec![
+            (u32::MAX, Box::new(SwapCount)),
+            (2, Box::new(SwapCount)),
+        ]);
+        assert_eq!(weighted.step_cost(&Step::Swap(1)), u32::MAX);
+        assert_eq!(weighted.step_cost(&Step::Pop), 0);
+
+        let weighted = Weighted::new(vec![(3, Box::new(SwapCount)), (2, Box::new(SwapCount))]);
+        assert_eq!(weighted.step_cost(&Step::Swap(1)), 5);
+    }
+
+    #[test]
+    fn test_huge_weights_dont_overflow() {
+        for src in SMALL_EXAMPLES {
+            for block in get_blocks(src) {
+                let cost_model = Weighted::new(vec![
+                    (
+                        1_000_000_000,
+                        Box::new(GasCost::new(
+                            block.sources.iter().map(ValueSource::gas).collect(),
+                            &block.graph,
+                        )),
+                    ),
+                    (
+                        1,
+                        Box::new(BytecodeSize::new(
+                            block.sources.iter().map(ValueSource::byte_size).collect(),
+                            &block.graph,
+                        )),
+                    ),
+                ]);
+                let graph = &block.graph;
+                let budget = SearchBudget {
+                    timeout: None,
+                    max_explored: Some(10_000),
+                };
+                let (steps, _) = Guessooor::new(0.035)
+                    .schedule(graph, &cost_model, 1024, budget)
+                    .unwrap();
+                LowerBound
+                    .schedule(graph, &cost_model, 1024, budget)
+                    .unwrap();
+                IterativeDeepening::new(LowerBound, 1 << 20)
+                    .schedule(graph, &cost_model, 1024, budget)
+                    .unwrap();
+                HashDistributed::new(LowerBound, 2)
+                    .schedule(graph, &cost_model, 1024, budget)
+                    .unwrap();
+                BeamScheduler::new(Guessooor::new(0.035), 4)
+                    .schedule(graph, &cost_model, 1024, budget)
+                    .unwrap();
+                reoptimize_windows(graph, &cost_model, steps, 4, 1024, &mut SearchArena::new());
+            }
+        }
+    }
+}
@@ -0,0 +1,58 @@
+use crate::scheduling::ir::CompNodeId;
+use std::fmt;
+
+/// Maximum stack depth allowed by the EVM.
+pub const MAX_STACK_DEPTH: usize = 1024;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ScheduleError {
+    /// (max_stack_depth) No schedule keeps the stack within the max depth.
+    StackTooDeep(usize),
+    /// (max_stack_depth) Requested max depth is above what the EVM allows.
+    InvalidMaxStackDepth(usize),
+    /// The values left on the stack can't be rearranged into the expected input layout.
+    UnreachableTargetLayout,
+    /// (depth, node) Moving the node into place would take a swap deeper than `SWAP16`.
+    SwapTooDeep(usize, CompNodeId),
+    /// The search budget ran out before any schedule was found.
+    BudgetExhausted,
+}
+
+impl fmt::Display for ScheduleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::StackTooDeep(max_stack_depth) => write!(
+                f,
+                "No schedule keeps the stack within the max depth of {}",
+                max_stack_depth
+            ),
+            Self::InvalidMaxStackDepth(max_stack_depth) => write!(
+                f,
+                "Max stack depth of {} exceeds the EVM's limit of {}",
+                max_stack_depth, MAX_STACK_DEPTH
+            ),
+            Self::UnreachableTargetLayout => {
+                write!(
+                    f,
+                    "Values left on the stack don't match the expected inputs"
+                )
+            }
+            Self::SwapTooDeep(depth, _) => write!(
+                f,
+                "Reaching a value takes a swap of depth {} (max: 16)",
+                depth
+            ),
+            Self::BudgetExhausted => write!(f, "Search budget exhausted before finding a schedule"),
+        }
+    }
+}
+
+impl std::error::Error for ScheduleError {}
+
+pub(crate) fn check_max_stack_depth(max_stack_depth: usize) -> Result<(), ScheduleError> {
+    if max_stack_depth > MAX_STACK_DEPTH {
+        Err(ScheduleError::InvalidMaxStackDepth(max_stack_depth))
+    } else {
+        Ok(())
+    }
+}
@@ -0,0 +1,355 @@
+use crate::scheduling::astar::{hash_one_off, NoopHasher, ScheduleNode};
+use crate::scheduling::{BackwardsMachine, Step};
+use std::collections::{BinaryHeap, HashMap};
+use std::hash::BuildHasherDefault;
+use std::ops::Range;
+
+/// How a state was reached at the lowest cost found so far.
+#[derive(Debug, Clone)]
+struct Explored<R> {
+    /// Reference to the state the steps were taken from, `None` for the start state.
+    came_from: Option<R>,
+    cost: u32,
+    /// Start of the state's encoding, the encoding ending where the next entry's starts.
+    state_start: usize,
+    steps: Range<usize>,
+    /// Earlier entry whose encoding has the same hash.
+    next_with_hash: Option<usize>,
+}
+
+/// Explored states of a search, compared by a compact encoding of the whole state rather than
+/// their hash so that two different states can never be mistaken for one another. Entries are
+/// referenced by their index which stays the same when a state is reached more cheaply.
+///
+/// The encodings and steps of all entries are stored back to back in shared buffers, so that
+/// there's no allocation per entry to free once the search is done.
+#[derive(Debug, Clone)]
+pub struct ExploredStates<R> {
+    /// Latest entry for every hash of an encoded state.
+    buckets: HashMap<u64, usize, BuildHasherDefault<NoopHasher>>,
+    entries: Vec<Explored<R>>,
+    states: Vec<u8>,
+    /// Steps of all entries, including ones that have since been reached more cheaply.
+    steps: Vec<Step>,
+    /// Buffer states are encoded into to be looked up.
+    key: Vec<u8>,
+}
+
+/// Appends `value` using one byte if it's small enough, otherwise a marker byte followed by the
+/// full value.
+fn encode_value(value: usize, out: &mut Vec<u8>) {
+    if value < u8::MAX as usize {
+        out.push(value as u8);
+    } else {
+        out.push(u8::MAX);
+        out.extend_from_slice(&(value as u64).to_le_bytes());
+    }
+}
+
+fn decode_value(bytes: &mut &[u8]) -> usize {
+    let (first, rest) = bytes.split_first().expect("Truncated state encoding");
+    if *first < u8::MAX {
+        *bytes = rest;
+        *first as usize
+    } else {
+        let (value, rest) = rest.split_at(8);
+        *bytes = rest;
+        u64::from_le_bytes(value.try_into().unwrap()) as usize
+    }
+}
+
+/// Encodes the state such that different states (of the same graph) result in different bytes.
+fn encode_state(state: &BackwardsMachine, out: &mut Vec<u8>) {
+    encode_value(state.stack.len(), out);
+    for id in state.stack.iter() {
+        encode_value(*id, out);
+    }
+    for blocked_by in state.blocked_by.iter() {
+        encode_value(blocked_by.map_or(0, |b| b as usize + 1), out);
+    }
+}
+
+fn decode_state(mut bytes: &[u8]) -> BackwardsMachine {
+    let stack_len = decode_value(&mut bytes);
+    let stack = (0..stack_len).map(|_| decode_value(&mut bytes)).collect();
+    let mut blocked_by = vec![];
+    while !bytes.is_empty() {
+        blocked_by.push(decode_value(&mut bytes).checked_sub(1).map(|b| b as u32));
+    }
+    BackwardsMachine::new(stack, blocked_by)
+}
+
+impl<R: Copy> ExploredStates<R> {
+    pub fn new() -> Self {
+        Self {
+            buckets: HashMap::default(),
+            entries: vec![],
+            states: vec![],
+            steps: vec![],
+            key: vec![],
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Estimated bytes taken up by an entry for a state like `state`, without any steps. Besides
+    /// the entry and its encoding every state has a bucket, the map keeping some buckets free and
+    /// rounding their amount up to the next power of 2 at worst doubles it.
+    pub fn entry_bytes(state: &BackwardsMachine) -> usize {
+        let mut encoding = vec![];
+        encode_state(state, &mut encoding);
+        std::mem::size_of::<Explored<R>>()
+            + encoding.len()
+            + 2 * (std::mem::size_of::<(u64, usize)>() + 1)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.buckets.clear();
+        self.entries.clear();
+        self.states.clear();
+        self.steps.clear();
+    }
+
+    pub fn reserve(&mut self, additional: usize) {
+        self.buckets.reserve(additional);
+        self.entries.reserve(additional);
+    }
+
+    pub fn cost(&self, index: usize) -> u32 {
+        self.entries[index].cost
+    }
+
+    pub fn came_from(&self, index: usize) -> Option<R> {
+        self.entries[index].came_from
+    }
+
+    pub fn steps(&self, index: usize) -> &[Step] {
+        &self.steps[self.entries[index].steps.clone()]
+    }
+
+    pub fn state(&self, index: usize) -> BackwardsMachine {
+        let end = self
+            .entries
+            .get(index + 1)
+            .map_or(self.states.len(), |next| next.state_start);
+        decode_state(&self.states[self.entries[index].state_start..end])
+    }
+
+    /// Encodes `state` into `self.key`, returning its hash and index if already explored.
+    fn find(&mut self, state: &BackwardsMachine) -> (u64, Option<usize>) {
+        self.key.clear();
+        encode_state(state, &mut self.key);
+        let hash = hash_one_off(&self.key);
+        let mut next = self.buckets.get(&hash).copied();
+        while let Some(index) = next {
+            let end = self
+                .entries
+                .get(index + 1)
+                .map_or(self.states.len(), |next| next.state_start);
+            if self.states[self.entries[index].state_start..end] == self.key[..] {
+                return (hash, Some(index));
+            }
+            next = self.entries[index].next_with_hash;
+        }
+        (hash, None)
+    }
+
+    pub fn index_of(&mut self, state: &BackwardsMachine) -> Option<usize> {
+        self.find(state).1
+    }
+
+    /// Records that `state` was reached for `cost`, unless it was already reached at no higher
+    /// cost. Returns the state's index and whether a more expensive way to reach it was replaced.
+    pub fn reach(
+        &mut self,
+        state: &BackwardsMachine,
+        came_from: Option<R>,
+        steps: &[Step],
+        cost: u32,
+    ) -> Option<(usize, bool)> {
+        let (index, improved) = self.reach_or_find(state, came_from, steps, cost);
+        improved.map(|replaced| (index, replaced))
+    }
+
+    /// Like `reach` but also returns the state's index if it was already reached at no higher cost,
+    /// in which case there's no improvement (`None`).
+    pub fn reach_or_find(
+        &mut self,
+        state: &BackwardsMachine,
+        came_from: Option<R>,
+        steps: &[Step],
+        cost: u32,
+    ) -> (usize, Option<bool>) {
+        let (hash, found) = self.find(state);
+        if let Some(index) = found.filter(|index| self.entries[*index].cost <= cost) {
+            return (index, None);
+        }
+        let steps_start = self.steps.len();
+        self.steps.extend_from_slice(steps);
+        let steps = steps_start..self.steps.len();
+        match found {
+            Some(index) => {
+                let entry = &mut self.entries[index];
+                entry.came_from = came_from;
+                entry.cost = cost;
+                entry.steps = steps;
+                (index, Some(true))
+            }
+            None => {
+                let index = self.entries.len();
+                let state_start = self.states.len();
+                self.states.extend_from_slice(&self.key);
+                self.entries.push(Explored {
+                    came_from,
+                    cost,
+                    state_start,
+                    steps,
+                    next_with_hash: self.buckets.insert(hash, index),
+                });
+                (index, Some(false))
+            }
+        }
+    }
+}
+
+impl<R: Copy> Default for ExploredStates<R> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ExploredStates<usize> {
+    /// Follows the steps that reached the state at `index` back to the start, the steps being
+    /// returned in execution order.
+    pub fn trace_steps(&self, index: usize) -> Vec<Step> {
+        let mut all_steps = vec![];
+        let mut next = Some(index);
+        while let Some(index) = next {
+            all_steps.extend(self.steps(index).iter().rev().cloned());
+            next = self.came_from(index);
+        }
+        all_steps
+    }
+}
+
+/// Memory used by an A* search, reusable across searches. Everything a search keeps around lives
+/// in a few large buffers so it's cheap to free or reset, scheduling many blocks with the same
+/// arena only allocates once it needs more room than any search before.
+#[derive(Debug, Clone)]
+pub struct SearchArena<R = usize> {
+    pub(crate) explored: ExploredStates<R>,
+    pub(crate) queue: BinaryHeap<ScheduleNode>,
+}
+
+impl<R: Copy> SearchArena<R> {
+    pub fn new() -> Self {
+        Self {
+            explored: ExploredStates::new(),
+            queue: BinaryHeap::new(),
+        }
+    }
+
+    /// Clears the previous search, keeping the allocated memory.
+    pub fn reset(&mut self) {
+        self.explored.clear();
+        self.queue.clear();
+    }
+
+    pub(crate) fn reserve(&mut self, additional: usize) {
+        self.explored.reserve(additional);
+        self.queue.reserve(additional);
+    }
+}
+
+impl<R: Copy> Default for SearchArena<R> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::scheduling::astar::{AStarScheduler, SearchBudget};
+    use crate::scheduling::cost::SwapCount;
+    use crate::scheduling::schedulers::Dijkstra;
+    use crate::test_utils::get_blocks;
+
+    fn state(stack: Vec<usize>) -> BackwardsMachine {
+        BackwardsMachine::new(stack, vec![Some(0), None, Some(1)])
+    }
+
+    #[test]
+    fn test_encoding_roundtrip() {
+        let mut explored = ExploredStates::<usize>::new();
+        let states = [state(vec![]), state(vec![2, 0]), state(vec![300, 1, 1])];
+        for state in states.iter() {
+            explored.reach(state, None, &[], 0).unwrap();
+        }
+        for (index, state) in states.iter().enumerate() {
+            assert_eq!(explored.state(index), *state);
+        }
+    }
+
+    #[test]
+    fn test_shared_bucket() {
+        let mut explored = ExploredStates::<usize>::new();
+        let (a, b) = (state(vec![0, 1]), state(vec![1, 0]));
+        assert_eq!(explored.reach(&a, None, &[Step::Pop], 5), Some((0, false)));
+        // Make `b` hash into `a`'s bucket.
+        let (a_hash, _) = explored.find(&a);
+        let (b_hash, _) = explored.find(&b);
+        explored.buckets.insert(b_hash, 0);
+
+        assert_eq!(explored.index_of(&b), None);
+        assert_eq!(
+            explored.reach(&b, Some(0), &[Step::Swap(1)], 3),
+            Some((1, false))
+        );
+        // Both hashes lead to `b` first, `a` only being found after it.
+        explored.buckets.insert(a_hash, 1);
+        assert_eq!(explored.index_of(&a), Some(0));
+        assert_eq!(explored.index_of(&b), Some(1));
+
+        // Entries sharing a bucket are only replaced by their own state.
+        assert_eq!(explored.reach(&b, None, &[], 4), None);
+        assert_eq!(explored.reach(&a, Some(1), &[], 2), Some((0, true)));
+        assert_eq!(explored.cost(0), 2);
+        assert_eq!(explored.cost(1), 3);
+        assert_eq!(explored.steps(1), [Step::Swap(1)]);
+        assert_eq!(explored.state(0), a);
+        assert_eq!(explored.state(1), b);
+    }
+
+    #[test]
+    fn test_reused_arena() {
+        let blocks = get_blocks(include_str!("../../examples/Math.balls"));
+
+        let mut arena = SearchArena::new();
+        for _ in 0..2 {
+            for block in blocks.iter() {
+                let schedule = |arena: &mut SearchArena| {
+                    Dijkstra
+                        .schedule_in(
+                            arena,
+                            &block.graph,
+                            &SwapCount,
+                            1024,
+                            SearchBudget::default(),
+                        )
+                        .unwrap()
+                };
+                let (steps, tracker) = schedule(&mut arena);
+                let (fresh_steps, fresh_tracker) = schedule(&mut SearchArena::new());
+                assert_eq!(steps, fresh_steps);
+                assert_eq!(tracker.final_cost(), fresh_tracker.final_cost());
+            }
+        }
+    }
+}
@@ -0,0 +1,297 @@
+use super::actions::get_actions;
+use crate::scheduling::astar::{
+    debug_validate, final_swaps, greedy_schedule, AStarScheduler, Incumbent, SchedulingTracker,
+    SearchBudget,
+};
+use crate::scheduling::cost::{steps_cost, CostModel};
+use crate::scheduling::error::{check_max_stack_depth, ScheduleError};
+use crate::scheduling::explored::{ExploredStates, SearchArena};
+use crate::scheduling::ir::IRGraph;
+use crate::scheduling::{BackwardsMachine, ScheduleInfo, Step};
+
+/// Iterative deepening A* (IDA*), explores the graph depth-first up to a cost threshold that's
+/// raised until a schedule is found. Unlike A* only the current path has to be kept in memory, the
+/// rest of the given memory budget goes to a table of already expanded states so that the many
+/// paths leading to the same state aren't all explored. The table holds the arena's explored
+/// states, comparing whole states rather than their hash. Results are optimal if the wrapped
+/// scheduler's estimate never overestimates the remaining cost (e.g. `Dijkstra`).
+#[derive(Debug, Clone)]
+pub struct IterativeDeepening<S: AStarScheduler> {
+    heuristic: S,
+    /// Memory to use for the transposition table in bytes.
+    max_memory: usize,
+}
+
+impl<S: AStarScheduler> IterativeDeepening<S> {
+    pub fn new(heuristic: S, max_memory: usize) -> Self {
+        Self {
+            heuristic,
+            max_memory,
+        }
+    }
+}
+
+struct Deepening<'a, S: AStarScheduler> {
+    heuristic: &'a S,
+    info: ScheduleInfo<'a>,
+    max_stack_depth: usize,
+    threshold: u32,
+    /// Lowest score seen above the threshold, the next iteration's threshold.
+    next_threshold: Option<u32>,
+    /// Lowest cost at which a state was expanded in the current iteration.
+    expanded: &'a mut ExploredStates<usize>,
+    table_capacity: usize,
+    path: Vec<Step>,
+    tracker: SchedulingTracker,
+    budget: SearchBudget,
+    /// Cost of the best schedule found before searching, nothing as or more expensive is explored.
+    incumbent_cost: Option<u32>,
+    /// Last reason a state couldn't be continued from, reported if no schedule is found.
+    dead_end: Option<ScheduleError>,
+}
+
+impl<S: AStarScheduler> Deepening<'_, S> {
+    /// Explores the states reachable from `state` whose score is within the threshold, returning
+    /// the total cost once an end state is reached. The steps taken, including the end state's
+    /// final swaps, are left in `self.path`.
+    fn search(&mut self, state: &BackwardsMachine, cost: u32, at_end: bool) -> Option<u32> {
+        let score = cost.saturating_add(
+            self.heuristic
+                .estimate_remaining_cost(self.info, state, cost),
+        );
+        if score > self.threshold {
+            self.next_threshold = Some(self.next_threshold.map_or(score, |t| t.min(score)));
+            return None;
+        }
+        if at_end {
+            return match final_swaps(self.info, state) {
+                Ok((swaps, swap_cost)) => {
+                    // The path is in reverse order like the machine.
+                    self.path.extend(swaps.into_iter().rev());
+                    Some(cost.saturating_add(swap_cost))
+                }
+                Err(err) => {
+                    self.dead_end = Some(err);
+                    None
+                }
+            };
+        }
+        if self.tracker.budget_exhausted || self.incumbent_cost.is_some_and(|inc| cost >= inc) {
+            return None;
+        }
+        if self.budget.exhausted(&self.tracker) {
+            self.tracker.budget_exhausted = true;
+            return None;
+        }
+
+        // Reaching a state again at no lower cost would only repeat the earlier exploration.
+        // Once the table is full only the states already in it are kept track of.
+        let tracked =
+            self.expanded.len() < self.table_capacity || self.expanded.index_of(state).is_some();
+        if tracked && self.expanded.reach(state, None, &[], cost).is_none() {
+            return None;
+        }
+
+        let mut neighbours: Vec<_> = get_actions(self.info, state)
+            .filter_map(|action| {
+                let mut new_state = state.clone();
+                let mut steps = vec![];
+                let at_end = match new_state.apply(self.info, action, &mut steps) {
+                    Ok(at_end) => at_end,
+                    Err(err) => {
+                        self.dead_end = Some(err);
+                        return None;
+                    }
+                };
+                if new_state.stack.len() > self.max_stack_depth {
+                    return None;
+                }
+                self.tracker.total_explored += 1;
+                let new_cost = cost.saturating_add(steps_cost(self.info.cost_model, &steps));
+                Some((new_cost, at_end, new_state, steps))
+            })
+            .collect();
+        // Cheapest first to reach an end state as early as possible.
+        neighbours.sort_by_key(|(new_cost, at_end, _, _)| (*new_cost, !at_end));
+
+        for (new_cost, at_end, new_state, steps) in neighbours {
+            let path_len = self.path.len();
+            self.path.extend(steps);
+            if let Some(final_cost) = self.search(&new_state, new_cost, at_end) {
+                return Some(final_cost);
+            }
+            self.path.truncate(path_len);
+        }
+
+        None
+    }
+}
+
+impl<S: AStarScheduler> AStarScheduler for IterativeDeepening<S> {
+    /// Only the current path and the transposition table are kept in memory, the table being the
+    /// explored states of `arena`.
+    fn schedule_in(
+        self,
+        arena: &mut SearchArena,
+        graph: &IRGraph,
+        cost_model: &dyn CostModel,
+        max_stack_depth: usize,
+        budget: SearchBudget,
+    ) -> Result<(Vec<Step>, SchedulingTracker), ScheduleError> {
+        check_max_stack_depth(max_stack_depth)?;
+        let info = ScheduleInfo::new(graph, cost_model);
+        let start = BackwardsMachine::new(
+            graph.output_ids.iter().rev().cloned().collect(),
+            graph.nodes.iter().map(|node| node.blocked_by).collect(),
+        );
+        let table_capacity = self.max_memory / ExploredStates::<usize>::entry_bytes(&start);
+        let start_bound = self.lower_bound(info, &start);
+        let incumbent = budget
+            .is_limited()
+            .then(|| greedy_schedule(&self, info, &start, max_stack_depth))
+            .flatten();
+
+        arena.reset();
+        arena.explored.reserve(table_capacity);
+        let mut deepening = Deepening {
+            heuristic: &self.heuristic,
+            info,
+            max_stack_depth,
+            threshold: self.estimate_remaining_cost(info, &start, 0),
+            next_threshold: None,
+            expanded: &mut arena.explored,
+            table_capacity,
+            path: vec![],
+            tracker: SchedulingTracker::default(),
+            budget,
+            incumbent_cost: incumbent.as_ref().map(|inc| inc.cost),
+            dead_end: None,
+        };
+
+        loop {
+            if let Some(final_cost) = deepening.search(&start, 0, start.all_done()) {
+                let Deepening {
+                    path,
+                    expanded,
+                    mut tracker,
+                    ..
+                } = deepening;
+                // The machine runs backwards, the path's steps are in reverse order.
+                let all_steps: Vec<_> = path.into_iter().rev().collect();
+                debug_validate(graph, &all_steps);
+                let lower_bound = if self.admissible() {
+                    final_cost
+                } else {
+                    start_bound
+                };
+                tracker.record_end(
+                    final_cost.saturating_add(cost_model.fixed_cost()),
+                    lower_bound.saturating_add(cost_model.fixed_cost()),
+                    cost_model.unit(),
+                    table_capacity,
+                    expanded.len(),
+                );
+                return Ok((all_steps, tracker));
+            }
+
+            // Every schedule cheaper than the threshold would've been found by now.
+            let settled = self.admissible()
+                && deepening
+                    .incumbent_cost
+                    .is_some_and(|inc| deepening.threshold >= inc);
+            let next_threshold = deepening.next_threshold.take();
+            match next_threshold {
+                Some(next_threshold) if !deepening.tracker.budget_exhausted && !settled => {
+                    deepening.threshold = next_threshold;
+                    deepening.expanded.clear();
+                }
+                _ => {
+                    let Some(Incumbent { steps, cost }) = incumbent else {
+                        return Err(if deepening.tracker.budget_exhausted {
+                            ScheduleError::BudgetExhausted
+                        } else {
+                            deepening
+                                .dead_end
+                                .unwrap_or(ScheduleError::StackTooDeep(max_stack_depth))
+                        });
+                    };
+                    let exhausted = next_threshold.is_none() && !deepening.tracker.budget_exhausted;
+                    let lower_bound = if exhausted {
+                        // Nothing was cut off by the threshold, only states costing at least as
+                        // much as the incumbent were left out.
+                        cost
+                    } else if self.admissible() {
+                        deepening.threshold.max(start_bound)
+                    } else {
+                        start_bound
+                    };
+                    debug_validate(graph, &steps);
+                    let mut tracker = deepening.tracker;
+                    tracker.record_end(
+                        cost.saturating_add(cost_model.fixed_cost()),
+                        lower_bound.saturating_add(cost_model.fixed_cost()),
+                        cost_model.unit(),
+                        table_capacity,
+                        deepening.expanded.len(),
+                    );
+                    return Ok((steps, tracker));
+                }
+            }
+        }
+    }
+
+    fn estimate_remaining_cost(
+        &self,
+        info: ScheduleInfo,
+        state: &BackwardsMachine,
+        cost: u32,
+    ) -> u32 {
+        self.heuristic.estimate_remaining_cost(info, state, cost)
+    }
+
+    fn admissible(&self) -> bool {
+        self.heuristic.admissible()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::scheduling::cost::SwapCount;
+    use crate::scheduling::schedulers::{Dijkstra, LowerBound};
+    use crate::test_utils::{assert_matches_dijkstra, get_blocks, small_blocks, swap_schedule};
+
+    const MAX_MEMORY: usize = 1 << 20;
+
+    #[test]
+    fn test_matches_dijkstra() {
+        assert_matches_dijkstra(&small_blocks(), 1024, |graph, max_stack_depth| {
+            swap_schedule(
+                IterativeDeepening::new(Dijkstra, MAX_MEMORY),
+                graph,
+                max_stack_depth,
+            )
+        });
+    }
+
+    #[test]
+    fn test_without_table() {
+        // Without room for a single table entry every path is explored anew, but the search is
+        // still optimal.
+        let blocks = get_blocks(include_str!("../../examples/transfer_ma.balls"));
+        assert_matches_dijkstra(&blocks, 1024, |graph, max_stack_depth| {
+            swap_schedule(
+                IterativeDeepening::new(LowerBound, 0),
+                graph,
+                max_stack_depth,
+            )
+        });
+        let explored = |max_memory| {
+            let (_, tracker) = IterativeDeepening::new(LowerBound, max_memory)
+                .schedule(&blocks[0].graph, &SwapCount, 1024, SearchBudget::default())
+                .unwrap();
+            tracker.total_explored
+        };
+        assert!(explored(0) > explored(MAX_MEMORY));
+    }
+}
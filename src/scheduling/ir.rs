@@ -1,3 +1,5 @@
+use crate::parser::types::Span;
+
 pub type CompNodeId = usize;
 
 #[derive(Clone, Debug, Hash, PartialEq, Eq)]
@@ -7,6 +9,16 @@ pub struct CompNode {
     pub operands: Vec<CompNodeId>,
     /// Non-operand dependencies
     pub post: Vec<CompNodeId>,
+    /// Nodes representing the individual outputs of a multi-output node, ordered from the top of
+    /// the stack down. Empty for nodes with at most one output.
+    pub projections: Vec<CompNodeId>,
+    /// Set on projection nodes, the multi-output node the value is an output of.
+    pub projection_of: Option<CompNodeId>,
+    /// Whether the value is cheap and side-effect free enough that it may be computed again in
+    /// place of duplicating an existing copy.
+    pub rematerializable: bool,
+    /// Token span of the source the node was generated from, if any.
+    pub span: Option<Span>,
 }
 
 impl CompNode {
@@ -16,12 +28,25 @@ impl CompNode {
             produces_value,
             operands,
             post,
+            projections: vec![],
+            projection_of: None,
+            rematerializable: false,
+            span: None,
         }
     }
 
     pub fn lone(produces_value: bool) -> Self {
         Self::new(produces_value, vec![], vec![])
     }
+
+    /// Creates a node standing in for one of the outputs of the multi-output node `of`. Projections
+    /// are never computed on their own, they're all put on the stack at once by their parent.
+    pub fn projection(of: CompNodeId) -> Self {
+        Self {
+            projection_of: Some(of),
+            ..Self::lone(true)
+        }
+    }
 }
 
 #[derive(Clone, Debug, Hash, PartialEq, Eq)]
@@ -1,4 +1,6 @@
 use crate::scheduling::actions::Action;
+use crate::scheduling::cost::CostModel;
+use crate::scheduling::error::ScheduleError;
 use crate::scheduling::ir::{CompNode, CompNodeId, IRGraph};
 use crate::scheduling::Step;
 use crate::scheduling::Swapper;
@@ -9,14 +11,16 @@ pub struct ScheduleInfo<'a> {
     pub nodes: &'a [CompNode],
     pub target_input_stack: &'a [CompNodeId],
     pub variants: &'a [Option<Vec<usize>>],
+    pub cost_model: &'a dyn CostModel,
 }
 
-impl<'a> From<&'a IRGraph> for ScheduleInfo<'a> {
-    fn from(graph: &'a IRGraph) -> Self {
+impl<'a> ScheduleInfo<'a> {
+    pub fn new(graph: &'a IRGraph, cost_model: &'a dyn CostModel) -> Self {
         Self {
             nodes: graph.nodes.as_slice(),
             target_input_stack: graph.input_ids.as_slice(),
             variants: graph.variants.as_slice(),
+            cost_model,
         }
     }
 }
@@ -37,7 +41,7 @@ impl Ord for BackwardsMachine {
     }
 }
 
-const MAX_VALID_SWAP_DEPTH: usize = 16;
+pub(crate) const MAX_VALID_SWAP_DEPTH: usize = 16;
 
 impl BackwardsMachine {
     pub fn new(end_stack: Vec<CompNodeId>, blocked_by: Vec<Option<u32>>) -> Self {
@@ -60,14 +64,16 @@ impl BackwardsMachine {
         info: ScheduleInfo,
         action: Action,
         steps: &mut Vec<Step>,
-    ) -> Result<bool, String> {
+    ) -> Result<bool, ScheduleError> {
         match action {
             Action::Unpop(id) => self.unpop(info, id, steps),
             Action::UndoComp(id, stack_idx, undoing_as_variant) => {
                 self.undo_comp(info, id, stack_idx, steps, undoing_as_variant)
             }
             Action::UndoEffect(id) => self.undo_effect(info, id, steps),
+            Action::UndoMultiComp(id) => self.undo_multi_comp(info, id, steps),
             Action::Dedup(as_top_idx, other_idx) => self.dedup(info, as_top_idx, other_idx, steps),
+            Action::Rematerialize(id, stack_idx) => self.rematerialize(id, stack_idx, steps),
         };
 
         let at_end = self.all_done();
@@ -81,25 +87,24 @@ impl BackwardsMachine {
         &mut self,
         info: ScheduleInfo,
         steps: &mut Vec<Step>,
-    ) -> Result<(), String> {
-        if self.stack.is_empty() {
-            debug_assert_eq!(
-                info.target_input_stack.len(),
-                0,
-                "Ended with a stack of size 0 but target_input_stack has a non-zero length"
-            );
-        } else {
-            let mut swapper = Swapper::new(&mut self.stack, info.target_input_stack);
+    ) -> Result<(), ScheduleError> {
+        let target = info.target_input_stack;
+        if self.stack.len() != target.len() {
+            return Err(ScheduleError::UnreachableTargetLayout);
+        }
+        if !self.stack.is_empty() {
+            let mut swapper = Swapper::new(&mut self.stack, target);
             for depth in swapper.get_swaps() {
                 if depth > MAX_VALID_SWAP_DEPTH {
-                    return Err(format!("Invalid necessary swap depth: {}", depth));
+                    // The value meant to end up at that depth can't be brought there.
+                    let id = target[target.len() - 1 - depth];
+                    return Err(ScheduleError::SwapTooDeep(depth, id));
                 }
                 steps.push(Step::Swap(depth));
             }
-            assert!(
-                swapper.matching_count().unwrap(),
-                "Not-matching count according to swapper despite all_done => true"
-            );
+            if !swapper.matching_count().unwrap_or(false) {
+                return Err(ScheduleError::UnreachableTargetLayout);
+            }
         }
         Ok(())
     }
@@ -178,6 +183,90 @@ impl BackwardsMachine {
         steps.push(Step::Comp(id, false));
     }
 
+    fn undo_multi_comp(&mut self, info: ScheduleInfo, id: CompNodeId, steps: &mut Vec<Step>) {
+        debug_assert_eq!(
+            self.blocked_by[id],
+            Some(0),
+            "Undoing blocked/done element {}",
+            id
+        );
+        let projections = &info.nodes[id].projections;
+        let window_start = projections
+            .iter()
+            .map(|proj_id| {
+                debug_assert_eq!(
+                    self.blocked_by[*proj_id],
+                    Some(0),
+                    "Undoing multi-output node with blocked/done projection {}",
+                    proj_id
+                );
+                self.stack
+                    .iter()
+                    .index_of(proj_id)
+                    .unwrap_or_else(|| panic!("Projection {} not on stack", proj_id))
+            })
+            .min()
+            .expect("Multi-output node without projections");
+        debug_assert!(
+            self.stack.len() - window_start <= MAX_VALID_SWAP_DEPTH + 1,
+            "Balls too deep (projections spread over {} elements)",
+            self.stack.len() - window_start
+        );
+
+        // Rearrange the top of the stack such that the projections are in output order (first
+        // output at the top) without disturbing the order of the other elements.
+        let mut window = self.stack.split_off(window_start);
+        let target: Vec<_> = window
+            .iter()
+            .filter(|el_id| !projections.contains(el_id))
+            .chain(projections.iter().rev())
+            .copied()
+            .collect();
+        steps.extend(
+            Swapper::new(&mut window, &target)
+                .get_swaps()
+                .into_iter()
+                .map(Step::Swap),
+        );
+        self.stack
+            .extend(&target[..target.len() - projections.len()]);
+
+        for proj_id in projections.iter() {
+            self.blocked_by[*proj_id] = None;
+        }
+        self.blocked_by[id] = None;
+        self._undo_node(info, id, false);
+        steps.push(Step::Comp(id, false));
+    }
+
+    fn rematerialize(&mut self, id: CompNodeId, stack_idx: usize, steps: &mut Vec<Step>) {
+        // Every copy of a value other than the last one to be undone is accounted for by a dedup in
+        // its blocked count, computing the value again replaces one of those.
+        let blocked = self.blocked_by[id]
+            .as_mut()
+            .expect("Rematerializing done element");
+        debug_assert!(
+            *blocked > 0,
+            "Rematerializing element without other copies (id: {})",
+            id
+        );
+        *blocked -= 1;
+
+        let depth = self.stack.len() - 1 - stack_idx;
+        debug_assert!(depth <= 16, "Balls too deep {}", depth);
+        let actual_id = self.stack.swap_remove(stack_idx);
+        debug_assert_eq!(
+            actual_id, id,
+            "Id stack index mismatch depth: {}, passed id: {}, actual id: {}",
+            depth, id, actual_id
+        );
+
+        if depth > 0 {
+            steps.push(Step::Swap(depth));
+        }
+        steps.push(Step::Comp(id, false));
+    }
+
     fn dedup(
         &mut self,
         info: ScheduleInfo,
@@ -222,13 +311,21 @@ impl BackwardsMachine {
             steps.push(Step::Swap(swap_depth));
             self.stack.swap(as_top_idx, top_idx);
         }
+        // Deduping with the top copy itself, after the swap that copy sits at `as_top_idx`.
+        let other_idx = if other_idx == top_idx && swap_depth > 0 {
+            as_top_idx
+        } else {
+            other_idx
+        };
         let dedup_depth = top_idx - other_idx;
         debug_assert!(
             dedup_depth <= 16,
             "Balls too deep (attempted to dedup with depth: {})",
             dedup_depth
         );
-        self.blocked_by[id].as_mut().map(|b| *b -= 1);
+        if let Some(b) = self.blocked_by[id].as_mut() {
+            *b -= 1;
+        }
         steps.push(Step::Dup(dedup_depth));
         self.stack.pop();
         if self.blocked_by[id].unwrap() == 0 && info.target_input_stack.contains(&id) {
@@ -248,7 +345,9 @@ impl BackwardsMachine {
     fn _undo_node(&mut self, info: ScheduleInfo, id: CompNodeId, undoing_as_variant: bool) {
         let mut push_to_stack = |dep_id: &usize| {
             self.stack.push(*dep_id);
-            self.blocked_by[*dep_id].as_mut().map(|b| *b -= 1);
+            if let Some(b) = self.blocked_by[*dep_id].as_mut() {
+                *b -= 1;
+            }
             // Can mark operand as "done" if its blocked count is 0 and we know it doesn't need to
             // be undone because it's on the target input stack.
             if self.blocked_by[*dep_id].unwrap() == 0 && info.target_input_stack.contains(dep_id) {
@@ -271,7 +370,9 @@ impl BackwardsMachine {
         }
 
         for pre_id in info.nodes[id].post.iter() {
-            self.blocked_by[*pre_id].as_mut().map(|b| *b -= 1);
+            if let Some(b) = self.blocked_by[*pre_id].as_mut() {
+                *b -= 1;
+            }
         }
     }
 }
@@ -1,11 +1,20 @@
 pub mod actions;
 pub mod astar;
+pub mod auto_guess;
+pub mod beam;
+pub mod cost;
+pub mod error;
+pub mod explored;
+pub mod ida;
 pub mod ir;
 pub mod machine;
+pub mod parallel;
 pub mod schedulers;
 pub mod step;
 pub mod swap;
+pub mod window;
 
+pub use error::ScheduleError;
 pub use machine::{BackwardsMachine, ScheduleInfo};
 pub use step::Step;
 pub use swap::Swapper;
@@ -0,0 +1,445 @@
+use super::actions::get_actions;
+use crate::scheduling::astar::{
+    debug_validate, final_swaps, greedy_schedule, hash_one_off, AStarScheduler, Incumbent,
+    ScheduleNode, SchedulingTracker, SearchBudget,
+};
+use crate::scheduling::cost::{steps_cost, CostModel};
+use crate::scheduling::error::{check_max_stack_depth, ScheduleError};
+use crate::scheduling::explored::{ExploredStates, SearchArena};
+use crate::scheduling::ir::IRGraph;
+use crate::scheduling::{BackwardsMachine, ScheduleInfo, Step};
+use std::collections::BinaryHeap;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Barrier, Mutex};
+
+/// Nodes every worker expands between two synchronizations.
+const EXPANSIONS_PER_ROUND: usize = 64;
+
+/// Hash distributed A* (HDA*), every state is owned by one of the worker threads based on its hash.
+/// Workers expand the best nodes of the states they own and send the reached states to their
+/// owners, synchronizing in rounds. The search only ends once no worker has a node left that could
+/// lead to a cheaper schedule than the best one found, making results just as optimal as that of
+/// the wrapped scheduler.
+#[derive(Debug, Clone)]
+pub struct HashDistributed<S: AStarScheduler> {
+    heuristic: S,
+    threads: usize,
+}
+
+impl<S: AStarScheduler> HashDistributed<S> {
+    pub fn new(heuristic: S, threads: usize) -> Self {
+        Self {
+            heuristic,
+            threads: threads.max(1),
+        }
+    }
+}
+
+/// Worker owning a state and the state's index in that worker's explored states.
+type StateRef = (usize, usize);
+
+/// An `Option<u32>` that can be shared between workers. Every `u32` is a valid cost or score, `None`
+/// is kept apart by being stored as a value no `u32` converts to.
+struct AtomicOptionU32(AtomicU64);
+
+impl AtomicOptionU32 {
+    const NONE: u64 = u64::MAX;
+
+    fn new(value: Option<u32>) -> Self {
+        Self(AtomicU64::new(value.map_or(Self::NONE, u64::from)))
+    }
+
+    fn load(&self) -> Option<u32> {
+        let value = self.0.load(Ordering::SeqCst);
+        (value != Self::NONE).then_some(value as u32)
+    }
+
+    fn store(&self, value: Option<u32>) {
+        self.0
+            .store(value.map_or(Self::NONE, u64::from), Ordering::SeqCst);
+    }
+
+    fn into_inner(self) -> Option<u32> {
+        self.load()
+    }
+}
+
+/// A reached state sent to its owner.
+struct Reached {
+    state: BackwardsMachine,
+    cost: u32,
+    score: u32,
+    at_end: bool,
+    came_from: StateRef,
+    steps: Vec<Step>,
+}
+
+struct Partition {
+    queue: BinaryHeap<ScheduleNode>,
+    explored: ExploredStates<StateRef>,
+    improved: usize,
+}
+
+impl Partition {
+    fn new() -> Self {
+        Self {
+            queue: BinaryHeap::new(),
+            explored: ExploredStates::new(),
+            improved: 0,
+        }
+    }
+
+    fn receive(&mut self, reached: Reached) {
+        if let Some((index, improved)) = self.explored.reach(
+            &reached.state,
+            Some(reached.came_from),
+            &reached.steps,
+            reached.cost,
+        ) {
+            self.improved += if improved { 1 } else { 0 };
+            self.queue.push(ScheduleNode {
+                index,
+                cost: reached.cost,
+                score: reached.score,
+                at_end: reached.at_end,
+            });
+        }
+    }
+}
+
+struct SharedSearch<'a, S: AStarScheduler> {
+    heuristic: &'a S,
+    info: ScheduleInfo<'a>,
+    max_stack_depth: usize,
+    partitions: Vec<Mutex<Partition>>,
+    inboxes: Vec<Mutex<Vec<Reached>>>,
+    /// Best score left in each partition's queue, `None` if empty.
+    best_scores: Vec<AtomicOptionU32>,
+    /// Cost of the cheapest schedule found so far.
+    incumbent_cost: AtomicOptionU32,
+    incumbent: Mutex<Option<StateRef>>,
+    /// Lowest score left once the search ended, `None` if every queue ran empty.
+    final_score: AtomicOptionU32,
+    total_explored: AtomicUsize,
+    budget: SearchBudget,
+    tracker_start: std::time::Instant,
+    out_of_budget: AtomicBool,
+    /// Last reason a state couldn't be continued from, reported if no schedule is found.
+    dead_end: Mutex<Option<ScheduleError>>,
+    barrier: Barrier,
+}
+
+impl<S: AStarScheduler> SharedSearch<'_, S> {
+    fn owner(&self, state_hash: u64) -> usize {
+        (state_hash % self.partitions.len() as u64) as usize
+    }
+
+    fn work(&self, id: usize) {
+        let workers = self.partitions.len();
+        let mut explored_count = 0;
+        loop {
+            // 1. Take in the states reached during the last round.
+            let reached = std::mem::take(&mut *self.inboxes[id].lock().unwrap());
+            let mut partition = self.partitions[id].lock().unwrap();
+            for r in reached {
+                partition.receive(r);
+            }
+            let best_score = partition.queue.peek().map(|node| node.score);
+            self.best_scores[id].store(best_score);
+            let total_explored = self
+                .total_explored
+                .fetch_add(explored_count, Ordering::SeqCst)
+                + explored_count;
+            explored_count = 0;
+            // Decided by a single worker as the time elapsed differs between them.
+            if id == 0 && self.budget.exceeded(self.tracker_start, total_explored) {
+                self.out_of_budget.store(true, Ordering::SeqCst);
+            }
+            self.barrier.wait();
+
+            // 2. Every worker comes to the same conclusion as nothing changes until the next
+            //    barrier.
+            let incumbent_cost = self.incumbent_cost.load();
+            let lowest_score = self
+                .best_scores
+                .iter()
+                .filter_map(|score| score.load())
+                .min();
+            let settled =
+                lowest_score.is_none_or(|score| incumbent_cost.is_some_and(|inc| score >= inc));
+            if settled || self.out_of_budget.load(Ordering::SeqCst) {
+                self.final_score.store(lowest_score);
+                return;
+            }
+            self.barrier.wait();
+
+            // 3. Expand the best nodes, sending every reached state to its owner.
+            let mut outgoing: Vec<Vec<Reached>> = (0..workers).map(|_| vec![]).collect();
+            for _ in 0..EXPANSIONS_PER_ROUND {
+                let Some(node) = partition.queue.pop() else {
+                    break;
+                };
+                if self
+                    .incumbent_cost
+                    .load()
+                    .is_some_and(|inc| node.score >= inc)
+                {
+                    partition.queue.push(node);
+                    break;
+                }
+                // Skip nodes that were reached more cheaply after being queued.
+                if partition.explored.cost(node.index) < node.cost {
+                    continue;
+                }
+                let came_from = (id, node.index);
+                if node.at_end {
+                    let mut incumbent = self.incumbent.lock().unwrap();
+                    if self.incumbent_cost.load().is_none_or(|inc| node.cost < inc) {
+                        self.incumbent_cost.store(Some(node.cost));
+                        *incumbent = Some(came_from);
+                    }
+                    continue;
+                }
+
+                let state = partition.explored.state(node.index);
+                for action in get_actions(self.info, &state) {
+                    let mut new_state = state.clone();
+                    let mut steps = Vec::with_capacity(30);
+                    let at_end = match new_state.apply(self.info, action, &mut steps) {
+                        Ok(at_end) => at_end,
+                        Err(err) => {
+                            *self.dead_end.lock().unwrap() = Some(err);
+                            continue;
+                        }
+                    };
+                    if new_state.stack.len() > self.max_stack_depth {
+                        continue;
+                    }
+                    explored_count += 1;
+                    let new_cost = node
+                        .cost
+                        .saturating_add(steps_cost(self.info.cost_model, &steps));
+                    let score = new_cost.saturating_add(
+                        self.heuristic
+                            .estimate_remaining_cost(self.info, &new_state, new_cost),
+                    );
+                    let state_hash = hash_one_off(&new_state);
+                    outgoing[self.owner(state_hash)].push(Reached {
+                        state: new_state,
+                        cost: new_cost,
+                        score,
+                        at_end,
+                        came_from,
+                        steps,
+                    });
+                }
+            }
+            drop(partition);
+
+            for (owner, reached) in outgoing.into_iter().enumerate() {
+                if !reached.is_empty() {
+                    self.inboxes[owner].lock().unwrap().extend(reached);
+                }
+            }
+            self.barrier.wait();
+        }
+    }
+}
+
+impl<S: AStarScheduler> AStarScheduler for HashDistributed<S> {
+    /// Every worker keeps its states in memory of its own, `_arena` is left untouched. A worker's
+    /// states refer back to states owned by other workers, which the arena's explored states can't
+    /// express, and each worker has to be able to lock its memory independently of the others.
+    fn schedule_in(
+        self,
+        _arena: &mut SearchArena,
+        graph: &IRGraph,
+        cost_model: &dyn CostModel,
+        max_stack_depth: usize,
+        budget: SearchBudget,
+    ) -> Result<(Vec<Step>, SchedulingTracker), ScheduleError> {
+        check_max_stack_depth(max_stack_depth)?;
+        let mut tracker = SchedulingTracker::default();
+
+        let info = ScheduleInfo::new(graph, cost_model);
+        let start = BackwardsMachine::new(
+            graph.output_ids.iter().rev().cloned().collect(),
+            graph.nodes.iter().map(|node| node.blocked_by).collect(),
+        );
+        let start_bound = self.lower_bound(info, &start);
+        let seed = budget
+            .is_limited()
+            .then(|| greedy_schedule(&self, info, &start, max_stack_depth))
+            .flatten();
+
+        let search = SharedSearch {
+            heuristic: &self.heuristic,
+            info,
+            max_stack_depth,
+            partitions: (0..self.threads)
+                .map(|_| Mutex::new(Partition::new()))
+                .collect(),
+            inboxes: (0..self.threads).map(|_| Default::default()).collect(),
+            best_scores: (0..self.threads)
+                .map(|_| AtomicOptionU32::new(None))
+                .collect(),
+            incumbent_cost: AtomicOptionU32::new(seed.as_ref().map(|seed| seed.cost)),
+            incumbent: Mutex::new(None),
+            final_score: AtomicOptionU32::new(None),
+            total_explored: AtomicUsize::new(0),
+            budget,
+            tracker_start: tracker.start,
+            out_of_budget: AtomicBool::new(false),
+            dead_end: Mutex::new(None),
+            barrier: Barrier::new(self.threads),
+        };
+
+        let score = self.estimate_remaining_cost(info, &start, 0);
+        {
+            let mut partition = search.partitions[search.owner(hash_one_off(&start))]
+                .lock()
+                .unwrap();
+            let (index, _) = partition.explored.reach(&start, None, &[], 0).unwrap();
+            partition.queue.push(ScheduleNode {
+                index,
+                cost: 0,
+                score,
+                at_end: start.all_done(),
+            });
+        }
+
+        std::thread::scope(|scope| {
+            for id in 0..self.threads {
+                let search = &search;
+                scope.spawn(move || search.work(id));
+            }
+        });
+
+        let SharedSearch {
+            partitions,
+            incumbent,
+            incumbent_cost,
+            final_score,
+            total_explored,
+            out_of_budget,
+            dead_end,
+            ..
+        } = search;
+        let partitions: Vec<Partition> = partitions
+            .into_iter()
+            .map(|partition| partition.into_inner().unwrap())
+            .collect();
+
+        let (all_steps, final_cost) = match (incumbent.into_inner().unwrap(), seed) {
+            (Some((end_owner, end_index)), _) => {
+                let mut all_steps = vec![];
+                let mut next = Some((end_owner, end_index));
+                while let Some((owner, index)) = next {
+                    let explored = &partitions[owner].explored;
+                    all_steps.extend(explored.steps(index).iter().rev().cloned());
+                    next = explored.came_from(index);
+                }
+
+                let (swaps, swap_cost) =
+                    final_swaps(info, &partitions[end_owner].explored.state(end_index))?;
+                all_steps.extend(swaps);
+                let cost = incumbent_cost
+                    .into_inner()
+                    .expect("Incumbent without a cost");
+                (all_steps, cost.saturating_add(swap_cost))
+            }
+            (None, Some(Incumbent { steps, cost })) => (steps, cost),
+            (None, None) if out_of_budget.load(Ordering::SeqCst) => {
+                return Err(ScheduleError::BudgetExhausted)
+            }
+            (None, None) => {
+                return Err(dead_end
+                    .into_inner()
+                    .unwrap()
+                    .unwrap_or(ScheduleError::StackTooDeep(max_stack_depth)))
+            }
+        };
+        debug_validate(graph, &all_steps);
+        let lower_bound = if self.admissible() {
+            // With every queue empty nothing cheaper than the schedule found is left.
+            final_score
+                .into_inner()
+                .map_or(final_cost, |score| score.max(start_bound))
+        } else {
+            start_bound
+        };
+        tracker.budget_exhausted = out_of_budget.into_inner();
+
+        tracker.total_explored = total_explored.into_inner();
+        tracker.total_improved = partitions.iter().map(|p| p.improved).sum();
+        let explored_size = partitions.iter().map(|p| p.explored.len()).sum();
+
+        tracker.record_end(
+            final_cost.saturating_add(cost_model.fixed_cost()),
+            lower_bound.saturating_add(cost_model.fixed_cost()),
+            cost_model.unit(),
+            0,
+            explored_size,
+        );
+        Ok((all_steps, tracker))
+    }
+
+    fn estimate_remaining_cost(
+        &self,
+        info: ScheduleInfo,
+        state: &BackwardsMachine,
+        cost: u32,
+    ) -> u32 {
+        self.heuristic.estimate_remaining_cost(info, state, cost)
+    }
+
+    fn admissible(&self) -> bool {
+        self.heuristic.admissible()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::scheduling::cost::{SwapCount, Weighted};
+    use crate::scheduling::schedulers::{Dijkstra, LowerBound};
+    use crate::scheduling::step::validate_steps;
+    use crate::test_utils::{
+        assert_matches_dijkstra, function_blocks, get_blocks, small_blocks, swap_schedule,
+        PERMUTATION,
+    };
+
+    #[test]
+    fn test_matches_dijkstra() {
+        for threads in [2, 4] {
+            assert_matches_dijkstra(&small_blocks(), 1024, |graph, max_stack_depth| {
+                swap_schedule(
+                    HashDistributed::new(Dijkstra, threads),
+                    graph,
+                    max_stack_depth,
+                )
+            });
+        }
+    }
+
+    #[test]
+    fn test_more_threads_than_states() {
+        // Most threads never own a state, they still have to notice the search ending.
+        let block = function_blocks(PERMUTATION, "F").remove(0);
+        let steps = swap_schedule(HashDistributed::new(LowerBound, 16), &block.graph, 1024);
+        assert_eq!(steps.len(), 2);
+    }
+
+    #[test]
+    fn test_saturated_scores() {
+        // Every swap costs the most a cost can be, saturating the score of all states after one.
+        let cost_model = Weighted::new(vec![(u32::MAX, Box::new(SwapCount))]);
+        for block in get_blocks(include_str!("../../examples/transfer_ma.balls")) {
+            let (steps, tracker) = HashDistributed::new(LowerBound, 2)
+                .schedule(&block.graph, &cost_model, 1024, SearchBudget::default())
+                .unwrap();
+            assert_eq!(validate_steps(&block.graph, &steps), Ok(()));
+            assert!(tracker.final_cost() > 0);
+        }
+    }
+}
@@ -1,4 +1,5 @@
-use crate::scheduling::ir::CompNodeId;
+use crate::scheduling::ir::{CompNodeId, IRGraph};
+use crate::scheduling::machine::MAX_VALID_SWAP_DEPTH;
 
 #[derive(Debug, PartialEq, Eq, Hash, Clone, Ord, PartialOrd)]
 pub enum Step {
@@ -8,13 +9,103 @@ pub enum Step {
     Comp(CompNodeId, bool),
 }
 
-impl Step {
-    pub fn cost(&self) -> u32 {
-        match self {
-            Self::Swap(_) => 1,
-            Self::Pop => 0,
-            Self::Dup(_) => 0,
-            Self::Comp(_, _) => 0,
+/// Executes `steps` on the graph's input stack, checking that every node is computed exactly once
+/// (unless it may be rematerialized) after its dependencies, from its operands, and that the
+/// outputs are left on the stack in order.
+pub fn validate_steps(graph: &IRGraph, steps: &[Step]) -> Result<(), String> {
+    let mut stack = graph.input_ids.clone();
+    let mut computed: Vec<bool> = graph
+        .nodes
+        .iter()
+        .enumerate()
+        .map(|(id, node)| node.blocked_by.is_none() || graph.input_ids.contains(&id))
+        .collect();
+
+    for (i, step) in steps.iter().enumerate() {
+        match *step {
+            Step::Swap(depth) => {
+                if depth == 0 || depth > MAX_VALID_SWAP_DEPTH || depth >= stack.len() {
+                    return Err(format!(
+                        "Step {}: swap{} with {} stack elements",
+                        i,
+                        depth,
+                        stack.len()
+                    ));
+                }
+                let top = stack.len() - 1;
+                stack.swap(top, top - depth);
+            }
+            Step::Dup(depth) => {
+                if depth == 0 || depth > MAX_VALID_SWAP_DEPTH || depth > stack.len() {
+                    return Err(format!(
+                        "Step {}: dup{} with {} stack elements",
+                        i,
+                        depth,
+                        stack.len()
+                    ));
+                }
+                stack.push(stack[stack.len() - depth]);
+            }
+            Step::Pop => {
+                if stack.pop().is_none() {
+                    return Err(format!("Step {}: pop from empty stack", i));
+                }
+            }
+            Step::Comp(id, as_variant) => {
+                let node = graph
+                    .nodes
+                    .get(id)
+                    .ok_or_else(|| format!("Step {}: unknown node {}", i, id))?;
+                if node.projection_of.is_some() || graph.input_ids.contains(&id) {
+                    return Err(format!("Step {}: node {} can't be computed", i, id));
+                }
+                if computed[id] && !node.rematerializable {
+                    return Err(format!("Step {}: node {} computed twice", i, id));
+                }
+                if let Some(pre_id) = node.post.iter().find(|pre_id| !computed[**pre_id]) {
+                    return Err(format!(
+                        "Step {}: node {} computed before its dependency {}",
+                        i, id, pre_id
+                    ));
+                }
+                let operands: Vec<CompNodeId> = match (as_variant, &graph.variants[id]) {
+                    (false, _) => node.operands.clone(),
+                    (true, Some(variant)) => variant.iter().map(|i| node.operands[*i]).collect(),
+                    (true, None) => {
+                        return Err(format!("Step {}: node {} has no variant", i, id));
+                    }
+                };
+                for operand in operands {
+                    let found = stack.pop();
+                    if found != Some(operand) {
+                        return Err(format!(
+                            "Step {}: node {} expected operand {}, found {:?}",
+                            i, id, operand, found
+                        ));
+                    }
+                }
+
+                computed[id] = true;
+                if node.produces_value {
+                    stack.push(id);
+                }
+                // Outputs of multi-output nodes are pushed deepest first.
+                for proj_id in node.projections.iter().rev() {
+                    computed[*proj_id] = true;
+                    stack.push(*proj_id);
+                }
+            }
         }
     }
+
+    if let Some(id) = computed.iter().position(|computed| !computed) {
+        return Err(format!("Node {} never computed", id));
+    }
+    if !stack.iter().rev().eq(graph.output_ids.iter()) {
+        return Err(format!(
+            "Final stack {:?} doesn't match outputs {:?}",
+            stack, graph.output_ids
+        ));
+    }
+    Ok(())
 }
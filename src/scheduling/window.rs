@@ -0,0 +1,260 @@
+use crate::scheduling::astar::{debug_validate, AStarScheduler, SearchBudget};
+use crate::scheduling::cost::{steps_cost, CostModel};
+use crate::scheduling::explored::SearchArena;
+use crate::scheduling::ir::{CompNode, CompNodeId, IRGraph};
+use crate::scheduling::schedulers::Dijkstra;
+use crate::scheduling::Step;
+use crate::transformer::ir_gen::set_blocked_count;
+use std::collections::HashSet;
+
+/// States a window's search may explore, past it the best schedule found is used.
+const WINDOW_MAX_EXPLORED: usize = 100_000;
+
+/// What re-optimizing a schedule saved.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct WindowSavings {
+    pub swaps: i64,
+    pub cost: u32,
+}
+
+/// Executes a single step on the stack (bottom first), returning the lowest stack index it reads
+/// or modifies (the stack's length if none).
+fn execute_step(graph: &IRGraph, stack: &mut Vec<CompNodeId>, step: &Step) -> usize {
+    let len = stack.len();
+    match *step {
+        Step::Swap(depth) => {
+            stack.swap(len - 1, len - 1 - depth);
+            len - 1 - depth
+        }
+        Step::Dup(depth) => {
+            stack.push(stack[len - depth]);
+            len - depth
+        }
+        Step::Pop => {
+            stack.pop();
+            len - 1
+        }
+        Step::Comp(id, _) => {
+            let node = &graph.nodes[id];
+            stack.truncate(len - node.operands.len());
+            if node.produces_value {
+                stack.push(id);
+            }
+            stack.extend(node.projections.iter().rev());
+            len - node.operands.len()
+        }
+    }
+}
+
+/// Builds the graph of everything computed by `window`, taking the stack `before` (bottom first)
+/// to the stack `after`. Node ids are kept the same such that the block's cost model still
+/// applies, nodes that aren't involved are marked as done. Windows whose inputs contain
+/// duplicates or that compute a value more than once can't be expressed, returning `None`.
+fn window_graph(
+    graph: &IRGraph,
+    window: &[Step],
+    before: &[CompNodeId],
+    after: &[CompNodeId],
+) -> Option<IRGraph> {
+    let mut involved: HashSet<CompNodeId> = HashSet::new();
+    for id in before.iter() {
+        if !involved.insert(*id) {
+            return None;
+        }
+    }
+    let mut computed = HashSet::new();
+    for step in window {
+        if let Step::Comp(id, _) = step {
+            if !involved.insert(*id) {
+                return None;
+            }
+            computed.insert(*id);
+            involved.extend(graph.nodes[*id].projections.iter());
+        }
+    }
+
+    let mut nodes: Vec<CompNode> = graph
+        .nodes
+        .iter()
+        .enumerate()
+        .map(|(id, node)| {
+            if computed.contains(&id) {
+                CompNode {
+                    projections: node.projections.clone(),
+                    ..CompNode::new(
+                        node.produces_value,
+                        node.operands.clone(),
+                        node.post
+                            .iter()
+                            .filter(|pre_id| involved.contains(pre_id))
+                            .copied()
+                            .collect(),
+                    )
+                }
+            } else if involved.contains(&id) && !before.contains(&id) {
+                // Output of a multi-output node computed in the window.
+                CompNode {
+                    projection_of: node.projection_of,
+                    ..CompNode::lone(true)
+                }
+            } else {
+                CompNode::lone(node.produces_value)
+            }
+        })
+        .collect();
+
+    let input_ids = before.to_vec();
+    let output_ids: Vec<_> = after.iter().rev().copied().collect();
+    set_blocked_count(&input_ids, &output_ids, &mut nodes);
+    for (id, node) in nodes.iter_mut().enumerate() {
+        if !involved.contains(&id) {
+            node.blocked_by = None;
+        }
+    }
+
+    Some(IRGraph {
+        input_ids,
+        output_ids,
+        nodes,
+        variants: graph
+            .variants
+            .iter()
+            .enumerate()
+            .map(|(id, variant)| variant.clone().filter(|_| computed.contains(&id)))
+            .collect(),
+    })
+}
+
+fn total_swaps(steps: &[Step]) -> i64 {
+    steps
+        .iter()
+        .filter(|step| matches!(step, Step::Swap(_)))
+        .count() as i64
+}
+
+/// Local improvement pass, slides a window of `window_size` consecutive steps over the schedule
+/// and replaces the steps with the cheapest ones found by `Dijkstra` that take the stack before
+/// the window to the stack after it. Only the part of the stack the window touches is rearranged.
+pub fn reoptimize_windows(
+    graph: &IRGraph,
+    cost_model: &dyn CostModel,
+    mut steps: Vec<Step>,
+    window_size: usize,
+    max_stack_depth: usize,
+    arena: &mut SearchArena,
+) -> (Vec<Step>, WindowSavings) {
+    let original_swaps = total_swaps(&steps);
+    let original_cost = steps_cost(cost_model, &steps);
+    let window_size = window_size.max(2);
+
+    let mut start = 0;
+    let mut before = graph.input_ids.clone();
+    while start + 1 < steps.len() {
+        let end = (start + window_size).min(steps.len());
+        let window = &steps[start..end];
+
+        let mut after = before.clone();
+        let lowest_touched = window.iter().fold(before.len(), |lowest, step| {
+            lowest.min(execute_step(graph, &mut after, step))
+        });
+
+        let improved = window_graph(
+            graph,
+            window,
+            &before[lowest_touched..],
+            &after[lowest_touched..],
+        )
+        .and_then(|window_graph| {
+            let (new_window, _) = Dijkstra
+                .schedule_in(
+                    arena,
+                    &window_graph,
+                    cost_model,
+                    max_stack_depth.saturating_sub(lowest_touched),
+                    SearchBudget {
+                        timeout: None,
+                        max_explored: Some(WINDOW_MAX_EXPLORED),
+                    },
+                )
+                .ok()?;
+            (steps_cost(cost_model, &new_window) < steps_cost(cost_model, window))
+                .then_some(new_window)
+        });
+
+        match improved {
+            // Try again from the same point as the steps after it may now be improvable too.
+            Some(new_window) => {
+                steps.splice(start..end, new_window);
+            }
+            None => {
+                execute_step(graph, &mut before, &steps[start]);
+                start += 1;
+            }
+        }
+    }
+    debug_validate(graph, &steps);
+
+    let savings = WindowSavings {
+        swaps: original_swaps - total_swaps(&steps),
+        cost: original_cost - steps_cost(cost_model, &steps),
+    };
+    (steps, savings)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::scheduling::beam::BeamScheduler;
+    use crate::scheduling::cost::SwapCount;
+    use crate::scheduling::schedulers::Guessooor;
+    use crate::scheduling::step::validate_steps;
+    use crate::test_utils::{
+        assert_never_beats_dijkstra, function_blocks, small_blocks, swap_schedule, PERMUTATION,
+    };
+
+    #[test]
+    fn test_window_never_worsens_schedule() {
+        assert_never_beats_dijkstra(&small_blocks(), 1024, |graph, max_stack_depth| {
+            let steps = swap_schedule(
+                BeamScheduler::new(Guessooor::new(0.035), 1),
+                graph,
+                max_stack_depth,
+            );
+            let original = steps_cost(&SwapCount, &steps);
+            let (steps, savings) = reoptimize_windows(
+                graph,
+                &SwapCount,
+                steps,
+                6,
+                max_stack_depth,
+                &mut SearchArena::new(),
+            );
+            assert_eq!(steps_cost(&SwapCount, &steps) + savings.cost, original);
+            steps
+        });
+    }
+
+    #[test]
+    fn test_permuted_outputs() {
+        let block = function_blocks(PERMUTATION, "F").remove(0);
+        let (mut steps, _) = Dijkstra
+            .schedule(&block.graph, &SwapCount, 1024, SearchBudget::default())
+            .unwrap();
+        assert_eq!(steps.len(), 2);
+        // Swapping back and forth first leaves the same stack for the windows to start from.
+        steps.splice(0..0, [Step::Swap(2), Step::Swap(2)]);
+        for window in [2, 3, 6] {
+            let (new_steps, savings) = reoptimize_windows(
+                &block.graph,
+                &SwapCount,
+                steps.clone(),
+                window,
+                1024,
+                &mut SearchArena::new(),
+            );
+            assert_eq!(validate_steps(&block.graph, &new_steps), Ok(()));
+            assert_eq!(new_steps.len(), 2, "Window size {}", window);
+            assert_eq!(savings, WindowSavings { swaps: 2, cost: 2 });
+        }
+    }
+}
@@ -0,0 +1,82 @@
+use crate::parser::types::Span;
+use crate::scheduling::Step;
+use crate::transformer::ir_gen::IRBlock;
+
+/// Token span of the source every step of a block was generated for. Steps only rearranging the
+/// stack are attributed to the operation they prepare, the next node computed in the block.
+pub fn step_spans(block: &IRBlock, steps: &[Step]) -> Vec<Option<Span>> {
+    let mut next_span = None;
+    let mut spans: Vec<_> = steps
+        .iter()
+        .rev()
+        .map(|step| {
+            if let Step::Comp(id, _) = step {
+                next_span = block.graph.nodes[*id].span.clone();
+            }
+            next_span.clone()
+        })
+        .collect();
+    spans.reverse();
+    spans
+}
+
+/// 1-based line and column of the character at `char_offset`.
+pub fn line_col(src: &str, char_offset: usize) -> (usize, usize) {
+    let mut line = 1;
+    let mut col = 1;
+    for c in src.chars().take(char_offset) {
+        if c == '\n' {
+            line += 1;
+            col = 1;
+        } else {
+            col += 1;
+        }
+    }
+    (line, col)
+}
+
+/// Converts a span of character offsets into one of byte offsets.
+pub fn byte_span(src: &str, char_span: &Span) -> Span {
+    let byte_offset = |char_offset| {
+        src.char_indices()
+            .nth(char_offset)
+            .map_or(src.len(), |(offset, _)| offset)
+    };
+    byte_offset(char_span.start)..byte_offset(char_span.end)
+}
+
+/// Formats byte spans as a Solidity-style source map, one `s:l:f` entry per instruction separated by
+/// `;`. Instructions without a span are mapped to `-1:-1:-1`. All spans are in source file 0, fields
+/// that are the same as in the previous entry are left out.
+pub fn solidity_source_map(spans: &[Option<Span>]) -> String {
+    let mut previous: [Option<String>; 3] = Default::default();
+    spans
+        .iter()
+        .map(|span| {
+            let fields = match span {
+                Some(span) => [
+                    span.start.to_string(),
+                    (span.end - span.start).to_string(),
+                    "0".to_string(),
+                ],
+                None => ["-1".to_string(), "-1".to_string(), "-1".to_string()],
+            };
+            let mut entry: Vec<_> = fields
+                .into_iter()
+                .zip(previous.iter_mut())
+                .map(|(field, previous)| {
+                    if previous.as_ref() == Some(&field) {
+                        String::new()
+                    } else {
+                        previous.insert(field).clone()
+                    }
+                })
+                .collect();
+            while entry.last().is_some_and(String::is_empty) {
+                entry.pop();
+            }
+            entry.join(":")
+        })
+        .collect::<Vec<_>>()
+        .join(";")
+}
@@ -0,0 +1,236 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::ops::Range;
+
+const INSERT_START: &str = "// balls-insert-start";
+const INSERT_END: &str = "// balls-insert-end";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SpliceError {
+    /// (region, line) Start marker without a matching end marker.
+    UnclosedRegion(Option<String>, usize),
+    /// (region, line) End marker without a start marker before it.
+    UnopenedRegion(Option<String>, usize),
+    /// (start region, end region, line of the end marker)
+    MismatchedEnd(Option<String>, Option<String>, usize),
+    /// (region, line of the second start marker)
+    DuplicateRegion(Option<String>, usize),
+    /// (macro) Neither a region named after the macro nor an unnamed region exists.
+    MissingRegion(String),
+}
+
+fn region_repr(region: &Option<String>) -> String {
+    match region {
+        Some(name) => format!("region \"{}\"", name),
+        None => "unnamed region".to_string(),
+    }
+}
+
+impl fmt::Display for SpliceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::UnclosedRegion(region, line) => write!(
+                f,
+                "Line {}: {} is never closed with \"{}\"",
+                line,
+                region_repr(region),
+                INSERT_END
+            ),
+            Self::UnopenedRegion(region, line) => write!(
+                f,
+                "Line {}: end of {} without a \"{}\" before it",
+                line,
+                region_repr(region),
+                INSERT_START
+            ),
+            Self::MismatchedEnd(start, end, line) => write!(
+                f,
+                "Line {}: end of {} inside of {}",
+                line,
+                region_repr(end),
+                region_repr(start)
+            ),
+            Self::DuplicateRegion(region, line) => {
+                write!(f, "Line {}: {} is defined twice", line, region_repr(region))
+            }
+            Self::MissingRegion(name) => write!(
+                f,
+                "No region for {} (expected \"{}: {}\" or an unnamed \"{}\")",
+                name, INSERT_START, name, INSERT_START
+            ),
+        }
+    }
+}
+
+impl std::error::Error for SpliceError {}
+
+/// Parses a marker line, returning the region it names (`None` for an unnamed marker).
+fn parse_marker(line: &str, marker: &str) -> Option<Option<String>> {
+    let rest = line.trim().strip_prefix(marker)?;
+    if rest.is_empty() {
+        return Some(None);
+    }
+    let name = rest.strip_prefix(':')?.trim();
+    Some((!name.is_empty()).then(|| name.to_string()))
+}
+
+/// Byte ranges of the contents of every region, between the start and end marker lines.
+fn find_regions(content: &str) -> Result<HashMap<Option<String>, Range<usize>>, SpliceError> {
+    let mut regions = HashMap::new();
+    // (region, first line number, start of its contents)
+    let mut open: Option<(Option<String>, usize, usize)> = None;
+    let mut offset = 0;
+    for (i, line) in content.split_inclusive('\n').enumerate() {
+        let line_number = i + 1;
+        if let Some(region) = parse_marker(line, INSERT_START) {
+            if let Some((open_region, open_line, _)) = open {
+                return Err(SpliceError::UnclosedRegion(open_region, open_line));
+            }
+            open = Some((region, line_number, offset + line.len()));
+        } else if let Some(region) = parse_marker(line, INSERT_END) {
+            let Some((open_region, _, start)) = open.take() else {
+                return Err(SpliceError::UnopenedRegion(region, line_number));
+            };
+            // An unnamed end marker closes any region.
+            if region.is_some() && region != open_region {
+                return Err(SpliceError::MismatchedEnd(open_region, region, line_number));
+            }
+            if regions.insert(open_region.clone(), start..offset).is_some() {
+                return Err(SpliceError::DuplicateRegion(open_region, line_number));
+            }
+        }
+        offset += line.len();
+    }
+    match open {
+        Some((region, line, _)) => Err(SpliceError::UnclosedRegion(region, line)),
+        None => Ok(regions),
+    }
+}
+
+/// Replaces the contents of the regions in `content` with the generated macros (given as
+/// `(name, code)`). Every macro goes into the region named after it (`// balls-insert-start: NAME`
+/// to `// balls-insert-end: NAME`), the macros without one all go into the unnamed region
+/// (`// balls-insert-start` to `// balls-insert-end`) if there is one. Regions of other macros are
+/// left untouched so that the output of several files can be spliced into the same file. Macros
+/// without a region are an error unless `append_missing` is set, in which case a new region is
+/// appended for each of them.
+pub fn splice_macros(
+    content: &str,
+    macros: &[(String, String)],
+    append_missing: bool,
+) -> Result<String, SpliceError> {
+    let regions = find_regions(content)?;
+
+    let mut replacements: Vec<(Range<usize>, String)> = vec![];
+    let mut unclaimed = vec![];
+    let mut appended = String::new();
+    for (name, code) in macros {
+        match regions.get(&Some(name.clone())) {
+            Some(range) => replacements.push((range.clone(), format!("{}\n", code))),
+            None if regions.contains_key(&None) => unclaimed.push(code.as_str()),
+            None if append_missing => appended.push_str(&format!(
+                "\n{}: {}\n{}\n{}: {}\n",
+                INSERT_START, name, code, INSERT_END, name
+            )),
+            None => return Err(SpliceError::MissingRegion(name.clone())),
+        }
+    }
+    if let Some(range) = regions.get(&None).filter(|_| !unclaimed.is_empty()) {
+        replacements.push((range.clone(), format!("{}\n", unclaimed.join("\n\n"))));
+    }
+
+    replacements.sort_by_key(|(range, _)| range.start);
+    let mut out = String::with_capacity(content.len());
+    let mut copied_until = 0;
+    for (range, replacement) in replacements {
+        out.push_str(&content[copied_until..range.start]);
+        out.push_str(&replacement);
+        copied_until = range.end;
+    }
+    out.push_str(&content[copied_until..]);
+    if !appended.is_empty() && !out.is_empty() && !out.ends_with('\n') {
+        out.push('\n');
+    }
+    out.push_str(&appended);
+    Ok(out)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn macros(names: &[&str]) -> Vec<(String, String)> {
+        names
+            .iter()
+            .map(|name| (name.to_string(), format!("#define macro {}() = {{}}", name)))
+            .collect()
+    }
+
+    #[test]
+    fn test_named_regions() {
+        let content = "a\n// balls-insert-start: B\nold\n// balls-insert-end: B\nc\n    // balls-insert-start: A\n// balls-insert-end\n";
+        assert_eq!(
+            splice_macros(content, &macros(&["A", "B"]), false).unwrap(),
+            "a\n// balls-insert-start: B\n#define macro B() = {}\n// balls-insert-end: B\nc\n    // balls-insert-start: A\n#define macro A() = {}\n// balls-insert-end\n"
+        );
+        // Regions of macros from other files are left alone.
+        assert_eq!(
+            splice_macros(content, &macros(&["A"]), false).unwrap(),
+            content.replace(
+                "A\n// balls-insert-end\n",
+                "A\n#define macro A() = {}\n// balls-insert-end\n"
+            )
+        );
+    }
+
+    #[test]
+    fn test_unnamed_and_appended_regions() {
+        let content = "// balls-insert-start\n// balls-insert-end\n// balls-insert-start: B\n// balls-insert-end: B";
+        assert_eq!(
+            splice_macros(content, &macros(&["A", "B", "C"]), false).unwrap(),
+            "// balls-insert-start\n#define macro A() = {}\n\n#define macro C() = {}\n// balls-insert-end\n// balls-insert-start: B\n#define macro B() = {}\n// balls-insert-end: B"
+        );
+
+        let content = "// balls-insert-start: A\n// balls-insert-end: A\n";
+        assert_eq!(
+            splice_macros(content, &macros(&["B"]), false),
+            Err(SpliceError::MissingRegion("B".into()))
+        );
+        assert_eq!(
+            splice_macros(content, &macros(&["B"]), true).unwrap(),
+            format!(
+                "{}\n// balls-insert-start: B\n#define macro B() = {{}}\n// balls-insert-end: B\n",
+                content
+            )
+        );
+    }
+
+    #[test]
+    fn test_invalid_markers() {
+        let splice = |content: &str| splice_macros(content, &macros(&["A"]), true);
+        assert_eq!(
+            splice("// balls-insert-start: A\n"),
+            Err(SpliceError::UnclosedRegion(Some("A".into()), 1))
+        );
+        assert_eq!(
+            splice("x\n// balls-insert-end: A\n"),
+            Err(SpliceError::UnopenedRegion(Some("A".into()), 2))
+        );
+        assert_eq!(
+            splice("// balls-insert-start: A\n// balls-insert-end: B\n"),
+            Err(SpliceError::MismatchedEnd(
+                Some("A".into()),
+                Some("B".into()),
+                2
+            ))
+        );
+        assert_eq!(
+            splice("// balls-insert-start: A\n// balls-insert-start: B\n"),
+            Err(SpliceError::UnclosedRegion(Some("A".into()), 1))
+        );
+        assert_eq!(
+            splice("// balls-insert-start\n// balls-insert-end\n// balls-insert-start\n// balls-insert-end\n"),
+            Err(SpliceError::DuplicateRegion(None, 4))
+        );
+    }
+}
@@ -0,0 +1,140 @@
+//! Helpers shared by the unit tests.
+use crate::parser::ast::Ast;
+use crate::parser::{lexer, parser, Spanned};
+use crate::scheduling::astar::{AStarScheduler, SearchBudget};
+use crate::scheduling::cost::{steps_cost, SwapCount};
+use crate::scheduling::ir::IRGraph;
+use crate::scheduling::schedulers::Dijkstra;
+use crate::scheduling::step::validate_steps;
+use crate::scheduling::Step;
+use crate::transformer::analysis::{validate_and_get_symbols, SemanticError, Symbol, Symbols};
+use crate::transformer::ir_gen::{gen_ir, IRBlock};
+
+pub fn parse(src: &str) -> Vec<Spanned<Ast>> {
+    let (tokens, lex_errs) = lexer::lex(src);
+    assert!(lex_errs.is_empty(), "Lexing failed: {:?}", lex_errs);
+    let tokens = tokens.unwrap().into_iter().map(|t| t.inner).collect();
+    let (ast_nodes, parse_errs) = parser::parse_tokens(tokens);
+    assert!(parse_errs.is_empty(), "Parsing failed: {:?}", parse_errs);
+    ast_nodes.unwrap()
+}
+
+pub fn get_symbols(src: &str) -> Symbols {
+    validate_and_get_symbols(parse(src)).unwrap()
+}
+
+pub fn semantic_errors(src: &str) -> Vec<SemanticError> {
+    validate_and_get_symbols(parse(src)).unwrap_err()
+}
+
+/// IR blocks of the function `name` in `src`.
+pub fn function_blocks(src: &str, name: &str) -> Vec<IRBlock> {
+    let symbols = get_symbols(src);
+    let Symbol::Function(func) = &symbols[name].inner else {
+        panic!("{} is not a function", name);
+    };
+    gen_ir(func, &symbols, false).unwrap()
+}
+
+/// IR blocks of all functions in `src`.
+pub fn get_blocks(src: &str) -> Vec<IRBlock> {
+    let symbols = get_symbols(src);
+    symbols
+        .values()
+        .filter_map(|symbol| match &symbol.inner {
+            Symbol::Function(func) => Some(gen_ir(func, &symbols, false).unwrap()),
+            _ => None,
+        })
+        .flatten()
+        .collect()
+}
+
+/// Examples small enough for Dijkstra to finish quickly.
+pub const SMALL_EXAMPLES: [&str; 11] = [
+    include_str!("../examples/Math.balls"),
+    include_str!("../examples/addmod.balls"),
+    include_str!("../examples/branching.balls"),
+    include_str!("../examples/commutative_gt_lt.balls"),
+    include_str!("../examples/create2_factory.balls"),
+    include_str!("../examples/destructuring.balls"),
+    include_str!("../examples/just_dup.balls"),
+    include_str!("../examples/just_pop.balls"),
+    include_str!("../examples/merkle.balls"),
+    include_str!("../examples/rearrange.balls"),
+    include_str!("../examples/transfer_ma.balls"),
+];
+
+/// `F`'s outputs are a permutation of its inputs, its start state is already done and only needs
+/// swapping into place.
+pub const PERMUTATION: &str = "fn F(a, b, c) -> (c, a, b) {}";
+
+/// Blocks of all `SMALL_EXAMPLES` and `PERMUTATION`.
+pub fn small_blocks() -> Vec<IRBlock> {
+    SMALL_EXAMPLES
+        .iter()
+        .chain([&PERMUTATION])
+        .flat_map(|src| get_blocks(src))
+        .collect()
+}
+
+/// Steps `scheduler` schedules `graph` with, counting swaps, checking that the tracker's final
+/// cost is theirs.
+pub fn swap_schedule<S: AStarScheduler>(
+    scheduler: S,
+    graph: &IRGraph,
+    max_stack_depth: usize,
+) -> Vec<Step> {
+    let (steps, tracker) = scheduler
+        .schedule(graph, &SwapCount, max_stack_depth, SearchBudget::default())
+        .unwrap();
+    assert_eq!(tracker.final_cost(), steps_cost(&SwapCount, &steps));
+    steps
+}
+
+/// Checks that the steps `schedule` returns for every block, given the max stack depth, are valid
+/// and cost as many swaps as Dijkstra's (or at least as many unless `optimal`).
+fn compare_with_dijkstra<F>(blocks: &[IRBlock], max_stack_depth: usize, optimal: bool, schedule: F)
+where
+    F: Fn(&IRGraph, usize) -> Vec<Step>,
+{
+    for block in blocks {
+        let optimal_cost = steps_cost(
+            &SwapCount,
+            &swap_schedule(Dijkstra, &block.graph, max_stack_depth),
+        );
+        let steps = schedule(&block.graph, max_stack_depth);
+        assert_eq!(validate_steps(&block.graph, &steps), Ok(()));
+        let cost = steps_cost(&SwapCount, &steps);
+        if optimal {
+            assert_eq!(
+                cost, optimal_cost,
+                "Cost mismatch (block: {:?})",
+                block.label
+            );
+        } else {
+            assert!(
+                cost >= optimal_cost,
+                "Cost {} below optimal {} (block: {:?})",
+                cost,
+                optimal_cost,
+                block.label
+            );
+        }
+    }
+}
+
+/// Asserts that `schedule` finds an optimal schedule for every block.
+pub fn assert_matches_dijkstra<F>(blocks: &[IRBlock], max_stack_depth: usize, schedule: F)
+where
+    F: Fn(&IRGraph, usize) -> Vec<Step>,
+{
+    compare_with_dijkstra(blocks, max_stack_depth, true, schedule);
+}
+
+/// Asserts that `schedule` finds a valid schedule for every block, never one beating the optimum.
+pub fn assert_never_beats_dijkstra<F>(blocks: &[IRBlock], max_stack_depth: usize, schedule: F)
+where
+    F: Fn(&IRGraph, usize) -> Vec<Step>,
+{
+    compare_with_dijkstra(blocks, max_stack_depth, false, schedule);
+}
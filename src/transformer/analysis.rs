@@ -1,4 +1,6 @@
-use crate::parser::ast::{Ast, Expr, Function, HuffMacro, MacroArg};
+use crate::parser::ast::{
+    Assignment, Ast, Expr, Function, HuffMacro, Inlining, MacroArg, Statement,
+};
 use crate::transformer::std_evm::{get_standard_opcodes_and_deps, Op};
 
 use crate::parser::types::Span;
@@ -9,7 +11,6 @@ use std::fmt::Debug;
 #[derive(Debug, Clone)]
 pub enum SemanticError {
     DuplicateTopLevelIdentifier(String, Span, Span),
-    HuffMacroMoreThanOneOut(Spanned<HuffMacro>),
     /// (duplicate_type, duplicate_instance)
     DuplicateIdentifier(String, Spanned<String>),
     AssigningToImmutableTopLevel(Span),
@@ -21,7 +22,21 @@ pub enum SemanticError {
     CallArgumentMismatch(usize, usize, String, String, Span),
     /// (callable type, callable identifier, call span)
     NoOutputFromCall(String, String, Span),
+    /// (expected, actual, callable type, callable identifier, call span)
+    OutputCountMismatch(usize, usize, String, String, Span),
     ReadAndWrite(Spanned<String>, Spanned<String>),
+    /// (callable type, call identifier)
+    InliningNonFunction(String, Spanned<String>),
+    /// (call identifier)
+    InliningControlFlow(Spanned<String>),
+    /// (import path, reason)
+    HuffImportFailed(Spanned<String>, String),
+    /// (macro identifier, declaration span) `extern` without a `stack(..)` that isn't imported.
+    MissingStackIO(String, Span),
+    /// (macro identifier, declared (in, out), imported (in, out), declaration span, import span)
+    ImportedStackMismatch(String, (u16, u16), (u16, u16), Span, Span),
+    /// (macro identifier, declared, imported, declaration span, import span)
+    ImportedArgumentMismatch(String, usize, usize, Span, Span),
 }
 
 #[derive(Clone, Debug)]
@@ -38,7 +53,7 @@ pub type Symbols = BTreeMap<String, Spanned<Symbol>>;
 fn validate_dependency_list(
     is_read: bool,
     symbols: &Symbols,
-    dependencies: &Vec<Spanned<String>>,
+    dependencies: &[Spanned<String>],
 ) -> Vec<SemanticError> {
     dependencies
         .iter()
@@ -62,8 +77,8 @@ fn validate_dependency_list(
 
 fn validate_reads_writes(
     symbols: &Symbols,
-    reads: &Vec<Spanned<String>>,
-    writes: &Vec<Spanned<String>>,
+    reads: &[Spanned<String>],
+    writes: &[Spanned<String>],
 ) -> Vec<SemanticError> {
     let mut errors = vec![];
     errors.extend(validate_dependency_list(true, symbols, reads));
@@ -106,14 +121,8 @@ fn check_duplicate_identifiers(
         .collect()
 }
 
-fn validate_huff_macro(symbols: &Symbols, span: &Span, hmacro: &HuffMacro) -> Vec<SemanticError> {
+fn validate_huff_macro(symbols: &Symbols, hmacro: &HuffMacro) -> Vec<SemanticError> {
     let mut errors = vec![];
-    if hmacro.stack_out > 1 {
-        errors.push(SemanticError::HuffMacroMoreThanOneOut(Spanned::new(
-            hmacro.clone(),
-            span.clone(),
-        )));
-    }
 
     errors.extend(check_duplicate_identifiers(
         "macro argument",
@@ -129,22 +138,71 @@ fn validate_huff_macro(symbols: &Symbols, span: &Span, hmacro: &HuffMacro) -> Ve
     errors
 }
 
+fn check_output_count(
+    expected_outputs: Option<usize>,
+    actual_outputs: usize,
+    callable_type: &str,
+    ident: &str,
+    span: &Span,
+) -> Option<SemanticError> {
+    let expected = expected_outputs?;
+    if expected == actual_outputs {
+        None
+    } else if actual_outputs == 0 {
+        Some(SemanticError::NoOutputFromCall(
+            callable_type.into(),
+            ident.into(),
+            span.clone(),
+        ))
+    } else {
+        Some(SemanticError::OutputCountMismatch(
+            expected,
+            actual_outputs,
+            callable_type.into(),
+            ident.into(),
+            span.clone(),
+        ))
+    }
+}
+
 fn validate_expression(
     func: &Function,
     top_level_symbols: &Symbols,
     local_symbols: &Vec<String>,
     expr: &Spanned<Expr>,
-    expecting_output: bool,
+    expected_outputs: Option<usize>,
     errors: &mut Vec<SemanticError>,
 ) {
     match &expr.inner {
         Expr::Call {
+            inlining,
             ident,
             macro_args,
             stack_args,
         } => {
             let maybe_symbol = top_level_symbols.get(&ident.inner);
             if let Some(symbol) = maybe_symbol {
+                if *inlining != Inlining::Default {
+                    let non_function_type = match &symbol.inner {
+                        Symbol::Function(callee) => {
+                            if *inlining == Inlining::Always
+                                && callee.body.iter().any(Statement::is_control_flow)
+                            {
+                                errors.push(SemanticError::InliningControlFlow(ident.clone()));
+                            }
+                            None
+                        }
+                        Symbol::Op(_) => Some("opcode"),
+                        Symbol::HuffMacro(_) => Some("huff macro"),
+                        Symbol::Dependency | Symbol::Const => Some("non-callable"),
+                    };
+                    if let Some(callable_type) = non_function_type {
+                        errors.push(SemanticError::InliningNonFunction(
+                            callable_type.into(),
+                            ident.clone(),
+                        ));
+                    }
+                }
                 match &symbol.inner {
                     Symbol::Dependency | Symbol::Const => {
                         errors.push(SemanticError::CallingNonCallable(
@@ -167,13 +225,13 @@ fn validate_expression(
                                 expr.span.clone(),
                             ));
                         }
-                        if expecting_output && !stack_out {
-                            errors.push(SemanticError::NoOutputFromCall(
-                                "opcode".into(),
-                                ident.clone(),
-                                expr.span.clone(),
-                            ));
-                        }
+                        errors.extend(check_output_count(
+                            expected_outputs,
+                            *stack_out as usize,
+                            "opcode",
+                            ident,
+                            &expr.span,
+                        ));
                         if !macro_args.inner.is_empty() {
                             errors.push(SemanticError::CallArgumentMismatch(
                                 0,
@@ -209,13 +267,13 @@ fn validate_expression(
                                 expr.span.clone(),
                             ))
                         }
-                        if expecting_output && outputs.len() != 1 {
-                            errors.push(SemanticError::NoOutputFromCall(
-                                "function".into(),
-                                ident.clone(),
-                                expr.span.clone(),
-                            ));
-                        }
+                        errors.extend(check_output_count(
+                            expected_outputs,
+                            outputs.len(),
+                            "function",
+                            ident,
+                            &expr.span,
+                        ));
                     }
                     Symbol::HuffMacro(HuffMacro {
                         ident,
@@ -242,13 +300,13 @@ fn validate_expression(
                                 expr.span.clone(),
                             ))
                         }
-                        if expecting_output && *stack_out != 1 {
-                            errors.push(SemanticError::NoOutputFromCall(
-                                "huff macro".into(),
-                                ident.clone(),
-                                expr.span.clone(),
-                            ));
-                        }
+                        errors.extend(check_output_count(
+                            expected_outputs,
+                            *stack_out as usize,
+                            "huff macro",
+                            ident,
+                            &expr.span,
+                        ));
                     }
                 }
             } else {
@@ -280,7 +338,7 @@ fn validate_expression(
                     top_level_symbols,
                     local_symbols,
                     stack_arg,
-                    true,
+                    Some(1),
                     errors,
                 );
             }
@@ -309,9 +367,118 @@ fn validate_expression(
     }
 }
 
-fn validate_func(symbols: &Symbols, func: &Function) -> Vec<SemanticError> {
+fn validate_assignment(
+    func: &Function,
+    symbols: &Symbols,
+    local_symbols: &mut Vec<String>,
+    assignment: &Assignment,
+    errors: &mut Vec<SemanticError>,
+) {
+    validate_expression(
+        func,
+        symbols,
+        local_symbols,
+        &assignment.expr,
+        (!assignment.idents.is_empty()).then_some(assignment.idents.len()),
+        errors,
+    );
+    errors.extend(check_duplicate_identifiers(
+        "assignment target",
+        &assignment.idents.iter().collect(),
+    ));
+    for spanned_ident in assignment.idents.iter() {
+        if spanned_ident.inner == "_" {
+            continue;
+        }
+        if func
+            .macro_args
+            .iter()
+            .any(|macro_arg| macro_arg.inner == spanned_ident.inner)
+            || symbols.get(&spanned_ident.inner).is_some()
+        {
+            errors.push(SemanticError::AssigningToImmutableTopLevel(
+                spanned_ident.span.clone(),
+            ));
+        }
+        if !local_symbols.contains(&spanned_ident.inner) {
+            local_symbols.push(spanned_ident.inner.clone());
+        }
+    }
+}
+
+/// Validates a list of statements, adding newly assigned variables to `local_symbols`. Variables
+/// first assigned within a branch are only visible after the `if` if assigned in every branch,
+/// variables first assigned within a loop aren't visible after it.
+fn validate_body(
+    func: &Function,
+    symbols: &Symbols,
+    local_symbols: &mut Vec<String>,
+    body: &[Statement],
+    errors: &mut Vec<SemanticError>,
+) {
+    for statement in body.iter() {
+        match statement {
+            Statement::Assign(assignment) => {
+                validate_assignment(func, symbols, local_symbols, assignment, errors)
+            }
+            Statement::If {
+                condition,
+                then_body,
+                else_body,
+            } => {
+                validate_expression(func, symbols, local_symbols, condition, Some(1), errors);
+
+                let mut then_symbols = local_symbols.clone();
+                validate_body(func, symbols, &mut then_symbols, then_body, errors);
+                let mut else_symbols = local_symbols.clone();
+                if let Some(else_body) = else_body {
+                    validate_body(func, symbols, &mut else_symbols, else_body, errors);
+                }
+
+                for ident in then_symbols {
+                    if else_symbols.contains(&ident) && !local_symbols.contains(&ident) {
+                        local_symbols.push(ident);
+                    }
+                }
+            }
+            Statement::Loop {
+                carried,
+                condition,
+                body,
+            } => {
+                errors.extend(check_duplicate_identifiers(
+                    "loop variable",
+                    &carried.iter().collect(),
+                ));
+                for var in carried.iter() {
+                    if !local_symbols.contains(&var.inner) {
+                        errors.push(SemanticError::UndeclaredIdentifier(
+                            "local variable".into(),
+                            var.clone(),
+                        ));
+                    }
+                }
+                validate_expression(func, symbols, local_symbols, condition, Some(1), errors);
+
+                // The body may never run, variables it introduces don't outlive it.
+                let mut body_symbols = local_symbols.clone();
+                validate_body(func, symbols, &mut body_symbols, body, errors);
+            }
+        }
+    }
+}
+
+fn validate_func(symbols: &Symbols, func: &Function, span: &Span) -> Vec<SemanticError> {
     let mut errors = vec![];
 
+    // Like for calls marked `inline`, silently not inlining would be a surprise.
+    if func.inlining == Inlining::Always && func.body.iter().any(Statement::is_control_flow) {
+        errors.push(SemanticError::InliningControlFlow(Spanned::new(
+            func.ident.clone(),
+            span.clone(),
+        )));
+    }
+
     let func_args: Vec<_> = func.macro_args.iter().chain(func.inputs.iter()).collect();
 
     errors.extend(check_duplicate_identifiers("function argument", &func_args));
@@ -322,31 +489,7 @@ fn validate_func(symbols: &Symbols, func: &Function) -> Vec<SemanticError> {
         .map(|input| input.inner.clone())
         .collect();
 
-    for statement in func.body.iter() {
-        validate_expression(
-            func,
-            symbols,
-            &local_symbols,
-            &statement.expr,
-            statement.ident.is_some(),
-            &mut errors,
-        );
-        if let Some(spanned_ident) = &statement.ident {
-            if func
-                .macro_args
-                .iter()
-                .any(|macro_arg| macro_arg.inner == spanned_ident.inner)
-                || symbols.get(&spanned_ident.inner).is_some()
-            {
-                errors.push(SemanticError::AssigningToImmutableTopLevel(
-                    spanned_ident.span.clone(),
-                ));
-            }
-            if !local_symbols.contains(&spanned_ident.inner) {
-                local_symbols.push(spanned_ident.inner.clone());
-            }
-        }
-    }
+    validate_body(func, symbols, &mut local_symbols, &func.body, &mut errors);
 
     for output in &func.outputs {
         if !local_symbols.contains(&output.inner) {
@@ -409,6 +552,8 @@ pub fn validate_and_get_symbols(nodes: Vec<Spanned<Ast>>) -> Result<Symbols, Vec
         }
     }
 
+    // Definitions replaced by a later duplicate, still validated so their errors aren't lost.
+    let mut overwritten = vec![];
     let mut errors: Vec<SemanticError> = nodes
         .into_iter()
         .filter_map(|Spanned { inner: node, span }| {
@@ -417,24 +562,32 @@ pub fn validate_and_get_symbols(nodes: Vec<Spanned<Ast>>) -> Result<Symbols, Vec
                 Ast::Dependency(ident) => Some((ident, Symbol::Dependency)),
                 Ast::Function(func) => Some((func.ident.clone(), Symbol::Function(func))),
                 Ast::HuffMacro(hmacro) => Some((hmacro.ident.clone(), Symbol::HuffMacro(hmacro))),
-                Ast::Error => None,
+                Ast::HuffMacroDeps(deps) => {
+                    return Some(SemanticError::MissingStackIO(deps.ident, span))
+                }
+                Ast::HuffImport(_) | Ast::Error => None,
             }?;
             let duplicate_node =
                 symbols.insert(identifier.clone(), Spanned::new(symbol, span.clone()))?;
-            Some(SemanticError::DuplicateTopLevelIdentifier(
+            let error = SemanticError::DuplicateTopLevelIdentifier(
                 identifier,
-                duplicate_node.span,
+                duplicate_node.span.clone(),
                 span,
-            ))
+            );
+            overwritten.push(duplicate_node);
+            Some(error)
         })
         .collect();
-    errors.extend(symbols.values().flat_map(|symbol| {
-        match &symbol.inner {
-            Symbol::Function(func) => validate_func(&symbols, func),
-            Symbol::HuffMacro(hmacro) => validate_huff_macro(&symbols, &symbol.span, hmacro),
-            Symbol::Op(_) | Symbol::Const | Symbol::Dependency => vec![], // Nothing to validate, no errors
-        }
-    }));
+    errors.extend(
+        symbols
+            .values()
+            .chain(&overwritten)
+            .flat_map(|symbol| match &symbol.inner {
+                Symbol::Function(func) => validate_func(&symbols, func, &symbol.span),
+                Symbol::HuffMacro(hmacro) => validate_huff_macro(&symbols, hmacro),
+                Symbol::Op(_) | Symbol::Const | Symbol::Dependency => vec![], // Nothing to validate, no errors
+            }),
+    );
 
     if errors.is_empty() {
         Ok(symbols)
@@ -442,3 +595,100 @@ pub fn validate_and_get_symbols(nodes: Vec<Spanned<Ast>>) -> Result<Symbols, Vec
         Err(errors)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::{get_symbols, semantic_errors};
+
+    #[test]
+    fn test_destructuring_output_count() {
+        let errs = semantic_errors(
+            r#"
+            extern DIVMOD() stack(2, 2)
+            fn F(x, y) -> (a) {
+                (a, b, c) = DIVMOD(x, y)
+            }
+            fn G(x, y) -> (a) {
+                a = DIVMOD(x, y)
+            }
+            "#,
+        );
+        assert!(matches!(
+            &errs[..],
+            [
+                SemanticError::OutputCountMismatch(3, 2, _, ident_f, _),
+                SemanticError::OutputCountMismatch(1, 2, _, ident_g, _),
+            ] if ident_f == "DIVMOD" && ident_g == "DIVMOD"
+        ));
+
+        // Discarded outputs still count.
+        get_symbols(
+            r#"
+            extern DIVMOD() stack(2, 2)
+            fn F(x, y) -> (r) {
+                (_, r) = DIVMOD(x, y)
+            }
+            "#,
+        );
+    }
+
+    #[test]
+    fn test_inlining_control_flow() {
+        let errs = semantic_errors(
+            r#"
+            inline fn G(c) -> (x) {
+                if c { x = 1 } else { x = 2 }
+            }
+            fn H(c) -> (x) {
+                if c { x = 1 } else { x = 2 }
+            }
+            fn F(c) -> (x, y) {
+                x = G(c)
+                y = inline H(c)
+            }
+            "#,
+        );
+        assert!(matches!(
+            &errs[..],
+            [
+                SemanticError::InliningControlFlow(call),
+                SemanticError::InliningControlFlow(definition),
+            ] if call.inner == "H" && definition.inner == "G"
+        ));
+
+        // Control flow is fine without inlining.
+        get_symbols(
+            r#"
+            fn H(c) -> (x) {
+                if c { x = 1 } else { x = 2 }
+            }
+            fn F(c) -> (x) {
+                x = noinline H(c)
+            }
+            "#,
+        );
+    }
+
+    #[test]
+    fn test_duplicate_definitions_validated() {
+        let errs = semantic_errors(
+            r#"
+            fn F(a) -> (x) {
+                x = add(a, y)
+            }
+            fn F(a) -> (x) {
+                x = a
+            }
+            "#,
+        );
+        // The overwritten definition is still checked.
+        assert!(matches!(
+            &errs[..],
+            [
+                SemanticError::DuplicateTopLevelIdentifier(ident, _, _),
+                SemanticError::UndeclaredIdentifier(_, var),
+            ] if ident == "F" && var.inner == "y"
+        ));
+    }
+}
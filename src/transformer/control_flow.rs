@@ -0,0 +1,547 @@
+// Lowers structured control flow into straight-line basic blocks that can each be scheduled on
+// their own. Blocks agree on the stack layout at every edge between them: the variables live at
+// that point ordered by where they were first defined (top of the stack first).
+
+use crate::parser::ast::{Assignment, Expr, Function, Inlining, Statement};
+use crate::parser::Spanned;
+use std::collections::{BTreeSet, HashMap};
+
+#[derive(Clone, Debug)]
+pub enum Terminator {
+    /// Continues into the block that follows.
+    FallThrough,
+    /// Jumps to the label.
+    Jump(String),
+    /// Jumps to the label if the condition is non-zero, otherwise continues into the block that
+    /// follows.
+    JumpIf(Spanned<Expr>, String),
+}
+
+#[derive(Clone, Debug)]
+pub struct BasicBlock {
+    /// Label marking the block as a jump destination.
+    pub label: Option<String>,
+    /// Variables on the stack when entering the block, top of the stack first.
+    pub inputs: Vec<String>,
+    pub assignments: Vec<Assignment>,
+    /// Variables left on the stack when leaving the block, top of the stack first.
+    pub outputs: Vec<String>,
+    pub terminator: Terminator,
+}
+
+type Vars = BTreeSet<String>;
+
+struct Lowering {
+    blocks: Vec<BasicBlock>,
+    /// Order in which the function's local variables are first defined.
+    def_order: HashMap<String, usize>,
+    total_ifs: usize,
+    total_loops: usize,
+}
+
+impl Lowering {
+    fn new(func: &Function) -> Self {
+        let mut lowering = Self {
+            blocks: vec![],
+            def_order: HashMap::new(),
+            total_ifs: 0,
+            total_loops: 0,
+        };
+        for input in func.inputs.iter() {
+            lowering.define(&input.inner);
+        }
+        lowering.define_all(&func.body);
+        lowering
+    }
+
+    fn define(&mut self, ident: &String) {
+        if ident != "_" && !self.def_order.contains_key(ident) {
+            self.def_order.insert(ident.clone(), self.def_order.len());
+        }
+    }
+
+    fn define_all(&mut self, body: &[Statement]) {
+        for statement in body {
+            match statement {
+                Statement::Assign(assignment) => {
+                    for ident in assignment.idents.iter() {
+                        self.define(&ident.inner);
+                    }
+                }
+                Statement::If {
+                    then_body,
+                    else_body,
+                    ..
+                } => {
+                    self.define_all(then_body);
+                    self.define_all(else_body.as_deref().unwrap_or_default());
+                }
+                Statement::Loop { body, .. } => self.define_all(body),
+            }
+        }
+    }
+
+    fn layout(&self, vars: Vars) -> Vec<String> {
+        let mut layout: Vec<_> = vars.into_iter().collect();
+        layout.sort_by_key(|var| self.def_order[var]);
+        layout
+    }
+
+    /// Layout at the start of every iteration: the carried variables as declared followed by any
+    /// other variables live throughout the loop.
+    fn loop_layout(&self, carried: &[Spanned<String>], vars: Vars) -> Vec<String> {
+        let mut layout: Vec<String> = carried.iter().map(|var| var.inner.clone()).collect();
+        let rest = vars
+            .into_iter()
+            .filter(|var| !layout.contains(var))
+            .collect();
+        layout.extend(self.layout(rest));
+        layout
+    }
+
+    /// Variables live at the start of every iteration, given the ones needed after the loop.
+    fn loop_live(
+        &self,
+        carried: &[Spanned<String>],
+        condition: &Spanned<Expr>,
+        body: &[Statement],
+        live_after: &Vars,
+    ) -> Vars {
+        let mut live = live_after.clone();
+        live.extend(carried.iter().map(|var| var.inner.clone()));
+        self.expr_vars(&condition.inner, &mut live);
+        loop {
+            let body_live = self.live_in(body, &live);
+            if body_live.is_subset(&live) {
+                return live;
+            }
+            live.extend(body_live);
+        }
+    }
+
+    /// Collects the local variables referenced by an expression (macro args and constants aren't
+    /// kept on the stack).
+    fn expr_vars(&self, expr: &Expr, vars: &mut Vars) {
+        match expr {
+            Expr::Var(ident) => {
+                if self.def_order.contains_key(ident) {
+                    vars.insert(ident.clone());
+                }
+            }
+            Expr::Num(_) => {}
+            Expr::Call { stack_args, .. } => {
+                for arg in stack_args.inner.iter() {
+                    self.expr_vars(&arg.inner, vars);
+                }
+            }
+        }
+    }
+
+    /// Variables that need to be on the stack before `body` given the ones needed after it.
+    fn live_in(&self, body: &[Statement], live_out: &Vars) -> Vars {
+        body.iter()
+            .rev()
+            .fold(live_out.clone(), |mut live, statement| {
+                match statement {
+                    Statement::Assign(assignment) => {
+                        for ident in assignment.idents.iter() {
+                            live.remove(&ident.inner);
+                        }
+                        self.expr_vars(&assignment.expr.inner, &mut live);
+                    }
+                    Statement::If {
+                        condition,
+                        then_body,
+                        else_body,
+                    } => {
+                        let mut branches_live = self.live_in(then_body, &live);
+                        branches_live
+                            .extend(self.live_in(else_body.as_deref().unwrap_or_default(), &live));
+                        live = branches_live;
+                        self.expr_vars(&condition.inner, &mut live);
+                    }
+                    Statement::Loop {
+                        carried,
+                        condition,
+                        body,
+                    } => live = self.loop_live(carried, condition, body, &live),
+                }
+                live
+            })
+    }
+
+    fn terminator_vars(&self, terminator: &Terminator, vars: &mut Vars) {
+        if let Terminator::JumpIf(condition, _) = terminator {
+            self.expr_vars(&condition.inner, vars);
+        }
+    }
+
+    fn lower_body(
+        &mut self,
+        label: Option<String>,
+        inputs: Vec<String>,
+        body: &[Statement],
+        outputs: Vec<String>,
+        terminator: Terminator,
+    ) {
+        let mut live_out: Vars = outputs.iter().cloned().collect();
+        self.terminator_vars(&terminator, &mut live_out);
+
+        let mut label = label;
+        let mut inputs = inputs;
+        let mut assignments = vec![];
+
+        for (i, statement) in body.iter().enumerate() {
+            match statement {
+                Statement::Assign(assignment) => assignments.push(assignment.clone()),
+                Statement::If {
+                    condition,
+                    then_body,
+                    else_body,
+                } => {
+                    let live_after = self.live_in(&body[i + 1..], &live_out);
+                    let if_id = self.total_ifs;
+                    self.total_ifs += 1;
+                    let end_label = format!("if_{}_end", if_id);
+
+                    match else_body {
+                        Some(else_body) => {
+                            // [pre] jumpi(then) -> [else] jump(end) -> then: [then] -> end:
+                            let then_label = format!("if_{}_then", if_id);
+                            let mut split = self.live_in(then_body, &live_after);
+                            split.extend(self.live_in(else_body, &live_after));
+                            let split_layout = self.layout(split);
+                            let join_layout = self.layout(live_after);
+
+                            self.blocks.push(BasicBlock {
+                                label: label.take(),
+                                inputs: std::mem::take(&mut inputs),
+                                assignments: std::mem::take(&mut assignments),
+                                outputs: split_layout.clone(),
+                                terminator: Terminator::JumpIf(
+                                    condition.clone(),
+                                    then_label.clone(),
+                                ),
+                            });
+                            self.lower_body(
+                                None,
+                                split_layout.clone(),
+                                else_body,
+                                join_layout.clone(),
+                                Terminator::Jump(end_label.clone()),
+                            );
+                            self.lower_body(
+                                Some(then_label),
+                                split_layout,
+                                then_body,
+                                join_layout.clone(),
+                                Terminator::FallThrough,
+                            );
+                            inputs = join_layout;
+                        }
+                        None => {
+                            // [pre] jumpi(end, iszero(cond)) -> [then] -> end:
+                            // The skipped branch joins directly so both share one layout.
+                            let mut split = self.live_in(then_body, &live_after);
+                            split.extend(live_after);
+                            let split_layout = self.layout(split);
+
+                            self.blocks.push(BasicBlock {
+                                label: label.take(),
+                                inputs: std::mem::take(&mut inputs),
+                                assignments: std::mem::take(&mut assignments),
+                                outputs: split_layout.clone(),
+                                terminator: Terminator::JumpIf(
+                                    negate(condition),
+                                    end_label.clone(),
+                                ),
+                            });
+                            self.lower_body(
+                                None,
+                                split_layout.clone(),
+                                then_body,
+                                split_layout.clone(),
+                                Terminator::FallThrough,
+                            );
+                            inputs = split_layout;
+                        }
+                    }
+                    label = Some(end_label);
+                }
+                Statement::Loop {
+                    carried,
+                    condition,
+                    body: loop_body,
+                } => {
+                    // [pre] jumpi(end, iszero(cond)) -> start: [body] jumpi(start, cond) -> end:
+                    // The body receives and leaves the loop's layout, making the back-edge free.
+                    let live_after = self.live_in(&body[i + 1..], &live_out);
+                    let loop_id = self.total_loops;
+                    self.total_loops += 1;
+                    let start_label = format!("loop_{}_start", loop_id);
+                    let end_label = format!("loop_{}_end", loop_id);
+                    let layout = self.loop_layout(
+                        carried,
+                        self.loop_live(carried, condition, loop_body, &live_after),
+                    );
+
+                    self.blocks.push(BasicBlock {
+                        label: label.take(),
+                        inputs: std::mem::take(&mut inputs),
+                        assignments: std::mem::take(&mut assignments),
+                        outputs: layout.clone(),
+                        terminator: Terminator::JumpIf(negate(condition), end_label.clone()),
+                    });
+                    self.lower_body(
+                        Some(start_label.clone()),
+                        layout.clone(),
+                        loop_body,
+                        layout.clone(),
+                        Terminator::JumpIf(condition.clone(), start_label),
+                    );
+                    inputs = layout;
+                    label = Some(end_label);
+                }
+            }
+        }
+
+        self.blocks.push(BasicBlock {
+            label,
+            inputs,
+            assignments,
+            outputs,
+            terminator,
+        });
+    }
+}
+
+fn negate(condition: &Spanned<Expr>) -> Spanned<Expr> {
+    if let Expr::Call {
+        ident, stack_args, ..
+    } = &condition.inner
+    {
+        if ident.inner == "iszero" && stack_args.inner.len() == 1 {
+            return stack_args.inner[0].clone();
+        }
+    }
+    let span = condition.span.clone();
+    Spanned::new(
+        Expr::Call {
+            inlining: Inlining::Default,
+            ident: Spanned::new("iszero".into(), span.clone()),
+            macro_args: Spanned::new(vec![], span.clone()),
+            stack_args: Spanned::new(Box::new(vec![condition.clone()]), span.clone()),
+        },
+        span,
+    )
+}
+
+/// Splits a function's body into basic blocks, in the order they're to be emitted. A function
+/// without control flow results in a single block.
+pub fn lower_function(func: &Function) -> Vec<BasicBlock> {
+    let mut lowering = Lowering::new(func);
+    lowering.lower_body(
+        None,
+        func.inputs
+            .iter()
+            .map(|input| input.inner.clone())
+            .collect(),
+        &func.body,
+        func.outputs
+            .iter()
+            .map(|output| output.inner.clone())
+            .collect(),
+        Terminator::FallThrough,
+    );
+    lowering.blocks
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::get_symbols;
+    use crate::transformer::analysis::Symbol;
+
+    fn lower(src: &str) -> Vec<BasicBlock> {
+        let symbols = get_symbols(src);
+        let Symbol::Function(func) = &symbols["F"].inner else {
+            panic!("F is not a function");
+        };
+        let blocks = lower_function(func);
+        assert_edges_agree(&blocks);
+        blocks
+    }
+
+    /// Checks that every block leaves the stack in the layout its successors expect.
+    fn assert_edges_agree(blocks: &[BasicBlock]) {
+        let labelled = |label: &str| {
+            blocks
+                .iter()
+                .find(|block| block.label.as_deref() == Some(label))
+                .unwrap_or_else(|| panic!("No block labelled {}", label))
+        };
+        for (i, block) in blocks.iter().enumerate() {
+            let mut successors = vec![];
+            match &block.terminator {
+                Terminator::FallThrough => successors.extend(blocks.get(i + 1)),
+                Terminator::Jump(label) => successors.push(labelled(label)),
+                Terminator::JumpIf(_, label) => {
+                    successors.push(labelled(label));
+                    successors.push(&blocks[i + 1]);
+                }
+            }
+            for successor in successors {
+                assert_eq!(
+                    block.outputs, successor.inputs,
+                    "Layout mismatch from block {} to {:?}",
+                    i, successor.label
+                );
+            }
+        }
+    }
+
+    fn describe(blocks: &[BasicBlock]) -> Vec<String> {
+        blocks
+            .iter()
+            .map(|block| {
+                let terminator = match &block.terminator {
+                    Terminator::FallThrough => String::new(),
+                    Terminator::Jump(label) => format!(" jump({})", label),
+                    Terminator::JumpIf(_, label) => format!(" jumpi({})", label),
+                };
+                format!(
+                    "{}: [{}] -> [{}]{}",
+                    block.label.as_deref().unwrap_or("_"),
+                    block.inputs.join(", "),
+                    block.outputs.join(", "),
+                    terminator
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_if_else_layouts() {
+        let blocks = lower(
+            r#"
+            fn F(c, a) -> (x) {
+                b = add(a, 1)
+                if c { x = b } else { x = a }
+            }
+            "#,
+        );
+        assert_eq!(
+            describe(&blocks),
+            [
+                "_: [c, a] -> [a, b] jumpi(if_0_then)",
+                "_: [a, b] -> [x] jump(if_0_end)",
+                "if_0_then: [a, b] -> [x]",
+                "if_0_end: [x] -> [x]",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_else_if_layouts() {
+        let blocks = lower(
+            r#"
+            fn F(c, d, a) -> (x) {
+                if c { x = a } else if d { x = add(a, 1) } else { x = 0 }
+            }
+            "#,
+        );
+        assert_eq!(
+            describe(&blocks),
+            [
+                "_: [c, d, a] -> [d, a] jumpi(if_0_then)",
+                "_: [d, a] -> [a] jumpi(if_1_then)",
+                "_: [a] -> [x] jump(if_1_end)",
+                "if_1_then: [a] -> [x]",
+                "if_1_end: [x] -> [x] jump(if_0_end)",
+                "if_0_then: [d, a] -> [x]",
+                "if_0_end: [x] -> [x]",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_if_without_else_layouts() {
+        let blocks = lower(
+            r#"
+            fn F(c, d, a, b) -> (a, b) {
+                if c {
+                    a = add(a, b)
+                    if d { b = 0 }
+                }
+            }
+            "#,
+        );
+        // The skipped branches join directly so the layout is kept throughout, `d` included as
+        // it's on the stack wherever the outer branch is skipped.
+        assert_eq!(
+            describe(&blocks),
+            [
+                "_: [c, d, a, b] -> [d, a, b] jumpi(if_0_end)",
+                "_: [d, a, b] -> [d, a, b] jumpi(if_1_end)",
+                "_: [d, a, b] -> [d, a, b]",
+                "if_1_end: [d, a, b] -> [d, a, b]",
+                "if_0_end: [d, a, b] -> [a, b]",
+            ]
+        );
+        // Conditions are negated to jump past the branch.
+        let Terminator::JumpIf(condition, _) = &blocks[0].terminator else {
+            panic!("Expected a conditional jump");
+        };
+        assert!(matches!(&condition.inner, Expr::Call { ident, .. } if ident.inner == "iszero"));
+    }
+
+    #[test]
+    fn test_loop_layouts() {
+        let blocks = lower(
+            r#"
+            fn F(n, acc, k) -> (out) {
+                m = 5
+                i = 0
+                loop (i, acc) while lt(i, n) {
+                    acc = add(acc, k)
+                    i = add(i, 1)
+                }
+                out = mul(acc, m)
+            }
+            "#,
+        );
+        // Carried variables lead the layout, followed by `n`, `k` and `m` which are live across
+        // the back edge without being modified.
+        assert_eq!(
+            describe(&blocks),
+            [
+                "_: [n, acc, k] -> [i, acc, n, k, m] jumpi(loop_0_end)",
+                "loop_0_start: [i, acc, n, k, m] -> [i, acc, n, k, m] jumpi(loop_0_start)",
+                "loop_0_end: [i, acc, n, k, m] -> [out]",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_loop_in_branch_layouts() {
+        let blocks = lower(
+            r#"
+            fn F(c, a, b) -> (a) {
+                if c {
+                    loop (a) while lt(a, b) { a = add(a, 1) }
+                }
+            }
+            "#,
+        );
+        assert_eq!(
+            describe(&blocks),
+            [
+                "_: [c, a, b] -> [a, b] jumpi(if_0_end)",
+                "_: [a, b] -> [a, b] jumpi(loop_0_end)",
+                "loop_0_start: [a, b] -> [a, b] jumpi(loop_0_start)",
+                "loop_0_end: [a, b] -> [a, b]",
+                "if_0_end: [a, b] -> [a]",
+            ]
+        );
+    }
+}
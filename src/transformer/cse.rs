@@ -0,0 +1,171 @@
+// Common subexpression elimination, merges nodes that are guaranteed to compute the same value so
+// that the scheduler may reuse a value rather than being forced to compute it again.
+
+use crate::parser::Spanned;
+use crate::scheduling::ir::{CompNode, CompNodeId, IRGraph};
+use crate::transformer::analysis::{Symbol, Symbols};
+use crate::transformer::ir_gen::{set_blocked_count, IRBlock, ValueSource};
+use crate::transformer::std_evm::VOLATILE_OPS;
+use std::collections::HashMap;
+
+/// (source, operands, sorted non-operand dependencies)
+type NodeKey = (ValueSource, Vec<CompNodeId>, Vec<CompNodeId>);
+
+/// Whether two nodes with the same source, operands and non-operand dependencies always result in
+/// the same value. Ops that read a dependency but never write one qualify because a read's
+/// non-operand dependencies are the last writes to what it reads, two reads with the same ones
+/// can't have a write in between.
+fn is_mergeable(symbols: &Symbols, node: &CompNode, source: &ValueSource) -> bool {
+    if !node.produces_value || !node.projections.is_empty() {
+        return false;
+    }
+    match source {
+        ValueSource::MacroArg(_) | ValueSource::HuffConst(_) => true,
+        ValueSource::Op(ident) => matches!(
+            symbols.get(ident),
+            Some(Spanned {
+                inner: Symbol::Op(op),
+                ..
+            }) if op.writes.is_empty() && !VOLATILE_OPS.contains(&ident.as_str())
+        ),
+        _ => false,
+    }
+}
+
+pub fn eliminate_common_subexpressions(block: IRBlock, symbols: &Symbols) -> IRBlock {
+    let IRBlock {
+        label,
+        inputs,
+        graph,
+        sources,
+        assignments,
+    } = block;
+
+    let mut new_ids: Vec<CompNodeId> = Vec::with_capacity(graph.nodes.len());
+    let mut first_of: HashMap<NodeKey, CompNodeId> = HashMap::new();
+
+    let mut nodes: Vec<CompNode> = Vec::new();
+    let mut new_sources = Vec::new();
+    let mut variants = Vec::new();
+
+    // Nodes only ever depend on nodes created before them, processing them in order means every
+    // dependency has already been remapped.
+    for (id, node) in graph.nodes.iter().enumerate() {
+        let operands: Vec<_> = node.operands.iter().map(|dep| new_ids[*dep]).collect();
+        let mut post: Vec<CompNodeId> = Vec::with_capacity(node.post.len());
+        for dep in node.post.iter().map(|dep| new_ids[*dep]) {
+            if !post.contains(&dep) {
+                post.push(dep);
+            }
+        }
+
+        if is_mergeable(symbols, node, &sources[id]) {
+            let mut sorted_post = post.clone();
+            sorted_post.sort_unstable();
+            let key = (sources[id].clone(), operands.clone(), sorted_post);
+            if let Some(existing_id) = first_of.get(&key) {
+                new_ids.push(*existing_id);
+                continue;
+            }
+            first_of.insert(key, nodes.len());
+        }
+
+        new_ids.push(nodes.len());
+        nodes.push(CompNode {
+            projections: node.projections.clone(),
+            projection_of: node.projection_of.map(|of| new_ids[of]),
+            rematerializable: node.rematerializable,
+            span: node.span.clone(),
+            ..CompNode::new(node.produces_value, operands, post)
+        });
+        new_sources.push(match &sources[id] {
+            ValueSource::Projection(of, i) => ValueSource::Projection(new_ids[*of], *i),
+            source => source.clone(),
+        });
+        variants.push(graph.variants[id].clone());
+    }
+
+    // Projections are created after their multi-output node, only remappable now.
+    for node in nodes.iter_mut() {
+        for projection in node.projections.iter_mut() {
+            *projection = new_ids[*projection];
+        }
+    }
+
+    let input_ids: Vec<_> = graph.input_ids.iter().map(|id| new_ids[*id]).collect();
+    let output_ids: Vec<_> = graph.output_ids.iter().map(|id| new_ids[*id]).collect();
+
+    set_blocked_count(&input_ids, &output_ids, &mut nodes);
+
+    IRBlock {
+        label,
+        inputs,
+        graph: IRGraph {
+            input_ids,
+            output_ids,
+            nodes,
+            variants,
+        },
+        sources: new_sources,
+        assignments: assignments
+            .into_iter()
+            .map(|(ident, id)| (ident, new_ids[id]))
+            .collect(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::{function_blocks, get_symbols};
+
+    /// Number of `op` nodes left in `F` after eliminating common subexpressions.
+    fn count_after_cse(src: &str, op: &str) -> usize {
+        let symbols = get_symbols(src);
+        let mut blocks = function_blocks(src, "F");
+        assert_eq!(blocks.len(), 1);
+        let block = eliminate_common_subexpressions(blocks.remove(0), &symbols);
+        block
+            .sources
+            .iter()
+            .filter(|source| matches!(source, ValueSource::Op(ident) if ident == op))
+            .count()
+    }
+
+    #[test]
+    fn test_merges_pure_and_read_only() {
+        let src = r#"
+            fn F(a, b) -> (x, y, z, w) {
+                x = add(a, b)
+                y = add(a, b)
+                z = returndatasize()
+                w = returndatasize()
+            }
+        "#;
+        assert_eq!(count_after_cse(src, "add"), 1);
+        assert_eq!(count_after_cse(src, "returndatasize"), 1);
+    }
+
+    #[test]
+    fn test_volatile_not_merged() {
+        let src = r#"
+            fn F() -> (x, y) {
+                x = gas()
+                y = gas()
+            }
+        "#;
+        assert_eq!(count_after_cse(src, "gas"), 2);
+    }
+
+    #[test]
+    fn test_read_not_merged_across_write() {
+        let src = r#"
+            fn F(addr) -> (x, success, y) {
+                x = returndatasize()
+                success = call(gas(), addr, 0, 0, 0, 0, 0)
+                y = returndatasize()
+            }
+        "#;
+        assert_eq!(count_after_cse(src, "returndatasize"), 2);
+    }
+}
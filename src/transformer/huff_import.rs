@@ -0,0 +1,451 @@
+use crate::parser::ast::{Ast, HuffMacro};
+use crate::parser::Spanned;
+use crate::transformer::analysis::SemanticError;
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Macro or constant declared in a Huff file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HuffDeclaration {
+    Macro {
+        ident: String,
+        macro_args: Vec<String>,
+        takes: u16,
+        returns: u16,
+    },
+    Constant(String),
+}
+
+impl HuffDeclaration {
+    pub fn ident(&self) -> &str {
+        match self {
+            Self::Macro { ident, .. } | Self::Constant(ident) => ident,
+        }
+    }
+}
+
+/// Splits Huff source into words and punctuation characters (with the line they're on), skipping
+/// comments and string literals.
+fn huff_tokens(src: &str) -> Vec<(&str, usize)> {
+    let mut tokens = vec![];
+    let mut line = 1;
+    let mut chars = src.char_indices().peekable();
+    while let Some((start, c)) = chars.next() {
+        match c {
+            '\n' => line += 1,
+            c if c.is_whitespace() => {}
+            '/' if matches!(chars.peek(), Some((_, '/'))) => {
+                while chars.next_if(|(_, c)| *c != '\n').is_some() {}
+            }
+            '/' if matches!(chars.peek(), Some((_, '*'))) => {
+                chars.next();
+                let mut last = ' ';
+                for (_, c) in chars.by_ref() {
+                    if c == '\n' {
+                        line += 1;
+                    } else if last == '*' && c == '/' {
+                        break;
+                    }
+                    last = c;
+                }
+            }
+            '"' => {
+                for (_, c) in chars.by_ref() {
+                    match c {
+                        '"' => break,
+                        '\n' => line += 1,
+                        _ => {}
+                    }
+                }
+            }
+            c if c.is_ascii_alphanumeric() || c == '_' => {
+                let mut end = start + 1;
+                while let Some((i, _)) =
+                    chars.next_if(|(_, c)| c.is_ascii_alphanumeric() || *c == '_')
+                {
+                    end = i + 1;
+                }
+                tokens.push((&src[start..end], line));
+            }
+            _ => tokens.push((&src[start..start + c.len_utf8()], line)),
+        }
+    }
+    tokens
+}
+
+struct Cursor<'a> {
+    tokens: Vec<(&'a str, usize)>,
+    pos: usize,
+}
+
+impl<'a> Cursor<'a> {
+    fn peek(&self) -> Option<&'a str> {
+        self.tokens.get(self.pos).map(|(text, _)| *text)
+    }
+
+    fn error(&self, expected: &str) -> String {
+        match self.tokens.get(self.pos) {
+            Some((text, line)) => {
+                format!("Line {}: expected {}, found \"{}\"", line, expected, text)
+            }
+            None => format!("Expected {}, found end of file", expected),
+        }
+    }
+
+    fn eat(&mut self, text: &str) -> bool {
+        let matches = self.peek() == Some(text);
+        self.pos += matches as usize;
+        matches
+    }
+
+    fn expect(&mut self, text: &str) -> Result<(), String> {
+        if self.eat(text) {
+            Ok(())
+        } else {
+            Err(self.error(&format!("\"{}\"", text)))
+        }
+    }
+
+    fn ident(&mut self) -> Result<String, String> {
+        match self.peek() {
+            Some(text) if text.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') => {
+                self.pos += 1;
+                Ok(text.to_string())
+            }
+            _ => Err(self.error("identifier")),
+        }
+    }
+
+    fn stack_size(&mut self) -> Result<u16, String> {
+        let parsed = self.peek().and_then(|text| match text.strip_prefix("0x") {
+            Some(hex) => u16::from_str_radix(hex, 16).ok(),
+            None => text.parse().ok(),
+        });
+        let size = parsed.ok_or_else(|| self.error("stack size"))?;
+        self.pos += 1;
+        Ok(size)
+    }
+
+    /// Optional `takes(n)` / `returns(n)`, 0 if absent like in Huff.
+    fn stack_io(&mut self, keyword: &str) -> Result<u16, String> {
+        if !self.eat(keyword) {
+            return Ok(0);
+        }
+        self.expect("(")?;
+        let size = self.stack_size()?;
+        self.expect(")")?;
+        Ok(size)
+    }
+
+    /// Rest of a `#define macro NAME(args) = takes(n) returns(m)`, after the `macro` keyword.
+    fn huff_macro(&mut self) -> Result<HuffDeclaration, String> {
+        let ident = self.ident()?;
+        self.expect("(")?;
+        let mut macro_args = vec![];
+        if !self.eat(")") {
+            loop {
+                macro_args.push(self.ident()?);
+                if self.eat(")") {
+                    break;
+                }
+                self.expect(",")?;
+            }
+        }
+        self.expect("=")?;
+        let takes = self.stack_io("takes")?;
+        let returns = self.stack_io("returns")?;
+        Ok(HuffDeclaration::Macro {
+            ident,
+            macro_args,
+            takes,
+            returns,
+        })
+    }
+}
+
+/// Parses the `#define macro` and `#define constant` declarations of a Huff file, ignoring
+/// everything else. `#include`s are not followed.
+pub fn parse_huff_declarations(src: &str) -> Result<Vec<HuffDeclaration>, String> {
+    let mut cursor = Cursor {
+        tokens: huff_tokens(src),
+        pos: 0,
+    };
+    let mut declarations = vec![];
+    while let Some(text) = cursor.peek() {
+        cursor.pos += 1;
+        if text != "#" || !cursor.eat("define") {
+            continue;
+        }
+        if cursor.eat("macro") {
+            declarations.push(cursor.huff_macro()?);
+        } else if cursor.eat("constant") {
+            declarations.push(HuffDeclaration::Constant(cursor.ident()?));
+        }
+    }
+    Ok(declarations)
+}
+
+/// Replaces the `import huff` directives with the macros and constants declared in the imported
+/// files, read with `read_file`. `extern` declarations without a `stack(..)` take it from the
+/// imported macro, ones with it are checked against it. Identifiers defined in the BALLS file take
+/// precedence over imported ones, e.g. functions when importing the file they're spliced into.
+pub fn resolve_huff_imports(
+    nodes: Vec<Spanned<Ast>>,
+    mut read_file: impl FnMut(&str) -> std::io::Result<String>,
+) -> Result<Vec<Spanned<Ast>>, Vec<SemanticError>> {
+    let mut errors = vec![];
+    let mut imported: Vec<Spanned<HuffDeclaration>> = vec![];
+    for node in &nodes {
+        if let Ast::HuffImport(path) = &node.inner {
+            let declarations = read_file(&path.inner)
+                .map_err(|err| err.to_string())
+                .and_then(|src| parse_huff_declarations(&src));
+            match declarations {
+                Ok(declarations) => imported.extend(
+                    declarations
+                        .into_iter()
+                        .map(|declaration| Spanned::new(declaration, node.span.clone())),
+                ),
+                Err(reason) => errors.push(SemanticError::HuffImportFailed(path.clone(), reason)),
+            }
+        }
+    }
+
+    let mut imported_macros = BTreeMap::new();
+    for declaration in &imported {
+        if let HuffDeclaration::Macro {
+            ident,
+            macro_args,
+            takes,
+            returns,
+        } = &declaration.inner
+        {
+            imported_macros.entry(ident.clone()).or_insert((
+                macro_args.len(),
+                (*takes, *returns),
+                &declaration.span,
+            ));
+        }
+    }
+    let mut defined = BTreeSet::new();
+
+    let mut resolved = vec![];
+    for Spanned { inner: node, span } in nodes {
+        let node = match node {
+            Ast::HuffImport(_) => continue,
+            Ast::HuffMacroDeps(deps) => match imported_macros.get(&deps.ident) {
+                Some((arg_count, (takes, returns), import_span)) => {
+                    if *arg_count != deps.macro_args.len() {
+                        errors.push(SemanticError::ImportedArgumentMismatch(
+                            deps.ident.clone(),
+                            deps.macro_args.len(),
+                            *arg_count,
+                            span.clone(),
+                            (*import_span).clone(),
+                        ));
+                    }
+                    Ast::HuffMacro(HuffMacro {
+                        ident: deps.ident,
+                        macro_args: deps.macro_args,
+                        stack_in: *takes,
+                        stack_out: *returns,
+                        reads: deps.reads,
+                        writes: deps.writes,
+                    })
+                }
+                // Reported as missing its stack inputs and outputs by the analysis.
+                None => Ast::HuffMacroDeps(deps),
+            },
+            Ast::HuffMacro(hmacro) => {
+                if let Some((arg_count, stack_io, import_span)) = imported_macros.get(&hmacro.ident)
+                {
+                    if *stack_io != (hmacro.stack_in, hmacro.stack_out) {
+                        errors.push(SemanticError::ImportedStackMismatch(
+                            hmacro.ident.clone(),
+                            (hmacro.stack_in, hmacro.stack_out),
+                            *stack_io,
+                            span.clone(),
+                            (*import_span).clone(),
+                        ));
+                    }
+                    if *arg_count != hmacro.macro_args.len() {
+                        errors.push(SemanticError::ImportedArgumentMismatch(
+                            hmacro.ident.clone(),
+                            hmacro.macro_args.len(),
+                            *arg_count,
+                            span.clone(),
+                            (*import_span).clone(),
+                        ));
+                    }
+                }
+                Ast::HuffMacro(hmacro)
+            }
+            node => node,
+        };
+        match &node {
+            Ast::Const(ident) | Ast::Dependency(ident) => defined.insert(ident.clone()),
+            Ast::Function(func) => defined.insert(func.ident.clone()),
+            Ast::HuffMacro(hmacro) => defined.insert(hmacro.ident.clone()),
+            Ast::HuffMacroDeps(deps) => defined.insert(deps.ident.clone()),
+            Ast::HuffImport(_) | Ast::Error => false,
+        };
+        resolved.push(Spanned::new(node, span));
+    }
+
+    resolved.extend(
+        imported
+            .into_iter()
+            .filter(|declaration| !defined.contains(declaration.inner.ident()))
+            .map(|Spanned { inner, span }| {
+                let node = match inner {
+                    HuffDeclaration::Macro {
+                        ident,
+                        macro_args,
+                        takes,
+                        returns,
+                    } => Ast::HuffMacro(HuffMacro {
+                        ident,
+                        macro_args: macro_args
+                            .into_iter()
+                            .map(|arg| Spanned::new(arg, span.clone()))
+                            .collect(),
+                        stack_in: takes,
+                        stack_out: returns,
+                        reads: vec![],
+                        writes: vec![],
+                    }),
+                    HuffDeclaration::Constant(ident) => Ast::Const(ident),
+                };
+                Spanned::new(node, span)
+            }),
+    );
+
+    if errors.is_empty() {
+        Ok(resolved)
+    } else {
+        Err(errors)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parser::{lexer, parser};
+
+    const HUFF: &str = r##"
+#include "./other.huff" // #define macro COMMENTED() = takes(9) returns(9)
+#define constant OWNER_SLOT = FREE_STORAGE_POINTER()
+/* #define macro
+   BLOCK_COMMENTED() = takes(9) */
+#define function transfer(address, uint256) nonpayable returns ()
+#define macro _REQUIRE_NOT() = takes(1) returns(0) {
+    iszero is_ok jumpi
+        0x0 0x0 revert
+    is_ok:
+}
+#define macro _LOAD_ADDRESS(offset) = takes (0x0) returns (1) {
+    <offset> calldataload __FUNC_SIG("#define macro X()")
+}
+#define macro MAIN() = {}
+"##;
+
+    fn resolve(src: &str) -> Result<Vec<Spanned<Ast>>, Vec<SemanticError>> {
+        let tokens = lexer::lex(src).0.unwrap();
+        let (ast_nodes, errs) = parser::parse_tokens(tokens.into_iter().map(|t| t.inner).collect());
+        assert!(errs.is_empty(), "Parsing failed: {:?}", errs);
+        resolve_huff_imports(ast_nodes.unwrap(), |path| {
+            assert_eq!(path, "./lib.huff");
+            Ok(HUFF.to_string())
+        })
+    }
+
+    fn find_macro<'a>(nodes: &'a [Spanned<Ast>], name: &str) -> &'a HuffMacro {
+        nodes
+            .iter()
+            .find_map(|node| match &node.inner {
+                Ast::HuffMacro(hmacro) if hmacro.ident == name => Some(hmacro),
+                _ => None,
+            })
+            .unwrap()
+    }
+
+    #[test]
+    fn test_parse_huff_declarations() {
+        let huff_macro =
+            |ident: &str, macro_args: &[&str], takes, returns| HuffDeclaration::Macro {
+                ident: ident.into(),
+                macro_args: macro_args.iter().map(|arg| arg.to_string()).collect(),
+                takes,
+                returns,
+            };
+        assert_eq!(
+            parse_huff_declarations(HUFF).unwrap(),
+            vec![
+                HuffDeclaration::Constant("OWNER_SLOT".into()),
+                huff_macro("_REQUIRE_NOT", &[], 1, 0),
+                huff_macro("_LOAD_ADDRESS", &["offset"], 0, 1),
+                huff_macro("MAIN", &[], 0, 0),
+            ]
+        );
+        assert_eq!(
+            parse_huff_declarations("\n#define macro A(x,) = {}"),
+            Err("Line 2: expected identifier, found \")\"".into())
+        );
+    }
+
+    #[test]
+    fn test_resolve_huff_imports() {
+        let nodes = resolve(
+            r#"
+            import huff "./lib.huff"
+            extern _REQUIRE_NOT() reads(CONTROL_FLOW)
+            fn MAIN() -> () {}
+            "#,
+        )
+        .unwrap();
+        let require_not = find_macro(&nodes, "_REQUIRE_NOT");
+        assert_eq!((require_not.stack_in, require_not.stack_out), (1, 0));
+        assert_eq!(require_not.reads[0].inner, "CONTROL_FLOW");
+        let load_address = find_macro(&nodes, "_LOAD_ADDRESS");
+        assert_eq!(load_address.macro_args[0].inner, "offset");
+        assert!(load_address.reads.is_empty() && load_address.writes.is_empty());
+        // The BALLS function takes precedence over the imported macro.
+        assert!(!nodes
+            .iter()
+            .any(|node| matches!(&node.inner, Ast::HuffMacro(m) if m.ident == "MAIN")));
+        assert!(nodes
+            .iter()
+            .any(|node| matches!(&node.inner, Ast::Const(ident) if ident == "OWNER_SLOT")));
+
+        let errs = resolve(
+            r#"
+            import huff "./lib.huff"
+            extern _REQUIRE_NOT() stack(2, 0)
+            extern _LOAD_ADDRESS() reads(CALLDATA)
+            "#,
+        )
+        .unwrap_err();
+        assert!(matches!(
+            &errs[..],
+            [
+                SemanticError::ImportedStackMismatch(_, (2, 0), (1, 0), _, _),
+                SemanticError::ImportedArgumentMismatch(_, 0, 1, _, _),
+            ]
+        ));
+
+        // Both mismatches of one extern are reported.
+        let errs = resolve(
+            r#"
+            import huff "./lib.huff"
+            extern _LOAD_ADDRESS() stack(1, 1)
+            "#,
+        )
+        .unwrap_err();
+        assert!(matches!(
+            &errs[..],
+            [
+                SemanticError::ImportedStackMismatch(_, (1, 1), (0, 1), _, _),
+                SemanticError::ImportedArgumentMismatch(_, 0, 1, _, _),
+            ]
+        ));
+    }
+}
@@ -1,42 +1,49 @@
 // The computational graph can be considered the "IR" of balls.
 
-use crate::parser::ast::{Expr, Function, HuffMacro, MacroArg};
+use crate::parser::ast::{Assignment, Expr, Function, HuffMacro, Inlining, MacroArg, Statement};
+use crate::parser::types::Span;
 use crate::parser::Spanned;
 use crate::scheduling::ir::{CompNode, CompNodeId, IRGraph};
-use crate::transformer::analysis::{Symbol, Symbols};
-use crate::transformer::std_evm::Op;
+use crate::transformer::analysis::{SemanticError, Symbol, Symbols};
+use crate::transformer::control_flow::{lower_function, BasicBlock, Terminator};
+use crate::transformer::std_evm::{emitted_op, static_gas, Op};
 use std::collections::HashMap;
 use std::fmt::Debug;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum ValueSource {
     TopLevelInput(String),
     Op(String),
     MacroInvoke(String, Vec<MacroArg>),
     MacroArg(MacroArg),
     HuffConst(String),
+    /// (multi-output node, output index)
+    Projection(CompNodeId, usize),
+    /// Jump destination, pushes the address of the label.
+    Label(String),
 }
 
 impl ValueSource {
     pub fn huff_repr(&self, symbols: &Symbols, using_variant: bool) -> String {
         match self {
             Self::Op(ident) => {
-                if !using_variant {
-                    ident.clone()
+                let ident = if !using_variant {
+                    ident
                 } else {
                     match symbols.get(ident).expect("Invalid source identifier") {
                         Spanned {
                             inner: Symbol::Op(op),
                             ..
-                        } => op
-                            .other
-                            .as_ref()
-                            .expect("Using variant flag with non-variant op")
-                            .0
-                            .clone(),
+                        } => {
+                            &op.other
+                                .as_ref()
+                                .expect("Using variant flag with non-variant op")
+                                .0
+                        }
                         unexpected => panic!("Expected op symbol, not {:?}", unexpected),
                     }
-                }
+                };
+                emitted_op(ident).to_string()
             }
             Self::TopLevelInput(ident) => ident.clone(),
             Self::MacroInvoke(ident, args) => format!(
@@ -50,32 +57,118 @@ impl ValueSource {
 
             Self::MacroArg(arg) => arg.huff_repr(),
             Self::HuffConst(ident) => format!("[{}]", ident),
+            Self::Projection(_, _) => panic!("Projections are pushed by their multi-output node"),
+            Self::Label(ident) => ident.clone(),
+        }
+    }
+
+    /// Gas spent computing the value. Invoked macros are charged nothing as their cost doesn't
+    /// depend on the scheduling.
+    pub fn gas(&self) -> u32 {
+        match self {
+            Self::Op(ident) => static_gas(ident),
+            Self::MacroArg(MacroArg::Num(num)) if num.bits() == 0 => PUSH0_GAS,
+            Self::MacroArg(_) | Self::HuffConst(_) | Self::Label(_) => PUSH_GAS,
+            Self::MacroInvoke(_, _) | Self::TopLevelInput(_) | Self::Projection(_, _) => 0,
+        }
+    }
+
+    /// Bytes of code pushing the value. Literals are pushed with the smallest PUSH that fits them,
+    /// the values of constants and macro arguments aren't known here so they're assumed to need a
+    /// full PUSH32. Like with gas, invoked macros are charged nothing.
+    pub fn byte_size(&self) -> u32 {
+        match self {
+            Self::Op(_) => 1,
+            Self::MacroArg(MacroArg::Num(num)) => 1 + (num.bits() as u32).div_ceil(8),
+            Self::MacroArg(MacroArg::ArgRef(_)) | Self::HuffConst(_) => 1 + 32,
+            Self::Label(_) => 1 + LABEL_BYTES,
+            Self::MacroInvoke(_, _) | Self::TopLevelInput(_) | Self::Projection(_, _) => 0,
         }
     }
 }
 
+const PUSH0_GAS: u32 = 2;
+const PUSH_GAS: u32 = 3;
+/// Huff pushes jump labels with a PUSH2.
+pub(crate) const LABEL_BYTES: u32 = 2;
+
+/// Identifiers visible from within a function body, swapped out while graphing an inlined call.
+type Scope = (HashMap<String, MacroArg>, HashMap<String, CompNodeId>);
+
 #[derive(Clone, Debug, Default)]
 pub struct SemanticContext {
-    top_level_macro_args: Vec<String>,
+    /// Macro arguments in scope and what they resolve to in the top-level function.
+    macro_args: HashMap<String, MacroArg>,
     pub nodes_sources: Vec<(CompNode, ValueSource)>,
     ident_to_id: HashMap<String, CompNodeId>,
     last_write: HashMap<String, CompNodeId>,
     last_reads: HashMap<String, Vec<CompNodeId>>,
+    inline_by_default: bool,
+    /// Functions currently being inlined, recursive calls are never inlined.
+    inlining_stack: Vec<String>,
 }
 
 impl SemanticContext {
-    pub fn new(top_level_macro_args: Vec<String>) -> Self {
+    pub fn new(top_level_macro_args: Vec<String>, inline_by_default: bool) -> Self {
         Self {
-            top_level_macro_args,
+            macro_args: top_level_macro_args
+                .into_iter()
+                .map(|ident| (ident.clone(), MacroArg::ArgRef(ident)))
+                .collect(),
+            inline_by_default,
             ..Default::default()
         }
     }
+
+    fn enter_scope(&mut self, scope: Scope) -> Scope {
+        let (macro_args, idents) = scope;
+        (
+            std::mem::replace(&mut self.macro_args, macro_args),
+            std::mem::replace(&mut self.ident_to_id, idents),
+        )
+    }
+
+    fn exit_scope(&mut self, outer: Scope) {
+        self.enter_scope(outer);
+    }
+
+    /// Resolves a macro argument in the current scope to its top-level equivalent.
+    pub fn resolve_macro_arg(&self, arg: &MacroArg) -> MacroArg {
+        match arg {
+            MacroArg::Num(_) => arg.clone(),
+            MacroArg::ArgRef(ident) => self
+                .macro_args
+                .get(ident)
+                .unwrap_or_else(|| panic!("Encountered invalid macro arg in IR gen ({})", ident))
+                .clone(),
+        }
+    }
+
+    fn should_inline(&self, call_inlining: Inlining, callee: &Function) -> bool {
+        let inline = match (call_inlining, callee.inlining) {
+            (Inlining::Always, _) => true,
+            (Inlining::Never, _) => false,
+            (Inlining::Default, Inlining::Always) => true,
+            (Inlining::Default, Inlining::Never) => false,
+            (Inlining::Default, Inlining::Default) => self.inline_by_default,
+        };
+        inline
+            && !self.inlining_stack.contains(&callee.ident)
+            && !callee.body.iter().any(Statement::is_control_flow)
+    }
     pub fn add_node(&mut self, node: CompNode, value_source: ValueSource) -> CompNodeId {
         let id = self.nodes_sources.len();
         self.nodes_sources.push((node, value_source));
         id
     }
 
+    /// Attributes the nodes added since `first_id` that don't have a span yet to `span`.
+    fn span_new_nodes(&mut self, first_id: CompNodeId, span: &Span) {
+        for (node, _) in self.nodes_sources[first_id..].iter_mut() {
+            node.span.get_or_insert_with(|| span.clone());
+        }
+    }
+
     pub fn set_ident(&mut self, ident: String, id: CompNodeId) {
         self.ident_to_id.insert(ident, id);
     }
@@ -100,11 +193,8 @@ impl SemanticContext {
         if let Some(id) = self.ident_to_id.get(ident) {
             return Some(*id);
         }
-        if self.top_level_macro_args.contains(ident) {
-            let id = self.add_node(
-                CompNode::lone(true),
-                ValueSource::MacroArg(MacroArg::ArgRef(ident.clone())),
-            );
+        if let Some(arg) = self.macro_args.get(ident).cloned() {
+            let id = self.add_node(CompNode::lone(true), ValueSource::MacroArg(arg));
             return Some(id);
         }
         None
@@ -142,96 +232,270 @@ fn unspan<T: Clone + Debug>(spanned: &[Spanned<T>]) -> Vec<T> {
 }
 
 /// Graphs an expression object, transforming and creating nodes
-fn graph_expr(ctx: &mut SemanticContext, symbols: &Symbols, expr: &Expr) -> CompNodeId {
-    match expr {
+fn graph_expr(
+    ctx: &mut SemanticContext,
+    symbols: &Symbols,
+    expr: &Spanned<Expr>,
+) -> Result<CompNodeId, SemanticError> {
+    let first_id = ctx.nodes_sources.len();
+    let id = match &expr.inner {
         Expr::Var(ident) => ctx.get_with_symbols(symbols, ident).unwrap_or_else(|| {
             panic!(
                 "Encountered invalid identifier in IR gen ({}, {:?})",
-                ident, ctx.top_level_macro_args
+                ident, ctx.macro_args
             )
         }),
         Expr::Num(num) => ctx.add_node(
             CompNode::lone(true),
             ValueSource::MacroArg(MacroArg::Num(num.clone())),
         ),
-        Expr::Call {
+        Expr::Call { .. } => {
+            let output_ids = graph_call(ctx, symbols, expr)?;
+            debug_assert_eq!(
+                output_ids.len(),
+                1,
+                "Nested call doesn't have exactly one output"
+            );
+            output_ids[0]
+        }
+    };
+    ctx.span_new_nodes(first_id, &expr.span);
+    Ok(id)
+}
+
+/// Graphs a call, returning the IDs of the nodes representing its outputs (top of the stack
+/// first). Calls with more than one output get a projection node per output. Nodes are attributed
+/// to the span of the innermost expression they were generated for.
+fn graph_call(
+    ctx: &mut SemanticContext,
+    symbols: &Symbols,
+    expr: &Spanned<Expr>,
+) -> Result<Vec<CompNodeId>, SemanticError> {
+    let Expr::Call {
+        inlining,
+        ident,
+        macro_args,
+        stack_args,
+    } = &expr.inner
+    else {
+        return Ok(vec![graph_expr(ctx, symbols, expr)?]);
+    };
+    let first_id = ctx.nodes_sources.len();
+
+    let arg_ids: Vec<_> = stack_args
+        .inner
+        .iter()
+        .map(|e| graph_expr(ctx, symbols, e))
+        .collect::<Result<_, _>>()?;
+    let macro_args: Vec<_> = macro_args
+        .inner
+        .iter()
+        .map(|arg| ctx.resolve_macro_arg(&arg.inner))
+        .collect();
+    let symbol = symbols
+        .get(&ident.inner)
+        .expect("Encountered invalid identifier in IR gen");
+
+    if let Symbol::Function(callee) = &symbol.inner {
+        if ctx.should_inline(*inlining, callee) {
+            let output_ids = graph_inlined(ctx, symbols, callee, macro_args, arg_ids)?;
+            ctx.span_new_nodes(first_id, &expr.span);
+            return Ok(output_ids);
+        }
+    }
+
+    let (value_source, reads, writes, total_outputs): (
+        ValueSource,
+        Vec<String>,
+        Vec<String>,
+        usize,
+    ) = match &symbol.inner {
+        Symbol::Function(Function {
+            ident,
+            reads,
+            writes,
+            outputs,
+            ..
+        }) => (
+            ValueSource::MacroInvoke(ident.clone(), macro_args),
+            unspan(reads),
+            unspan(writes),
+            outputs.len(),
+        ),
+        Symbol::HuffMacro(HuffMacro {
             ident,
-            macro_args,
-            stack_args,
-        } => {
-            let arg_ids: Vec<_> = stack_args
-                .inner
-                .iter()
-                .map(|e| graph_expr(ctx, symbols, &e.inner))
+            reads,
+            writes,
+            stack_out,
+            ..
+        }) => (
+            ValueSource::MacroInvoke(ident.clone(), macro_args),
+            unspan(reads),
+            unspan(writes),
+            *stack_out as usize,
+        ),
+        Symbol::Op(Op {
+            ident,
+            reads,
+            writes,
+            stack_out,
+            ..
+        }) => (
+            ValueSource::Op(ident.clone()),
+            reads.clone(),
+            writes.clone(),
+            *stack_out as usize,
+        ),
+        other => panic!("Uncallable symbol in IR gen {:?}", other),
+    };
+
+    for arg_id in arg_ids.iter() {
+        debug_assert!(ctx.nodes_sources[*arg_id].0.produces_value);
+    }
+
+    let id = ctx.add_node(
+        CompNode::new(total_outputs == 1, arg_ids, vec![]),
+        value_source,
+    );
+
+    for r in reads.iter() {
+        ctx.record_read(r, id);
+    }
+    for w in writes.iter() {
+        ctx.record_write(w, id);
+    }
+
+    let output_ids = match total_outputs {
+        0 => vec![],
+        1 => vec![id],
+        _ => {
+            let projections: Vec<_> = (0..total_outputs)
+                .map(|i| ctx.add_node(CompNode::projection(id), ValueSource::Projection(id, i)))
                 .collect();
-            let symbol = symbols
-                .get(&ident.inner)
-                .expect("Encountered invalid identifier in IR gen");
-
-            let (value_source, reads, writes, produces_value): (
-                ValueSource,
-                Vec<String>,
-                Vec<String>,
-                bool,
-            ) = match &symbol.inner {
-                Symbol::Function(Function {
-                    ident,
-                    reads,
-                    writes,
-                    outputs,
-                    ..
-                }) => (
-                    ValueSource::MacroInvoke(ident.clone(), unspan(&macro_args.inner)),
-                    unspan(reads),
-                    unspan(writes),
-                    outputs.len() == 1,
-                ),
-                Symbol::HuffMacro(HuffMacro {
-                    ident,
-                    reads,
-                    writes,
-                    stack_out,
-                    ..
-                }) => (
-                    ValueSource::MacroInvoke(ident.clone(), unspan(&macro_args.inner)),
-                    unspan(reads),
-                    unspan(writes),
-                    *stack_out == 1,
-                ),
-                Symbol::Op(Op {
-                    ident,
-                    reads,
-                    writes,
-                    stack_out,
-                    ..
-                }) => (
-                    ValueSource::Op(ident.clone()),
-                    reads.clone(),
-                    writes.clone(),
-                    *stack_out,
-                ),
-                other => panic!("Uncallable symbol in IR gen {:?}", other),
-            };
-
-            for arg_id in arg_ids.iter() {
-                debug_assert!(ctx.nodes_sources[*arg_id].0.produces_value);
-            }
+            ctx.nodes_sources[id].0.projections = projections.clone();
+            projections
+        }
+    };
+    ctx.span_new_nodes(first_id, &expr.span);
+    Ok(output_ids)
+}
+
+/// Splices the body of `callee` into the current graph with its inputs wired to `arg_ids`,
+/// returning the IDs of the nodes computing its outputs.
+fn graph_inlined(
+    ctx: &mut SemanticContext,
+    symbols: &Symbols,
+    callee: &Function,
+    macro_args: Vec<MacroArg>,
+    arg_ids: Vec<CompNodeId>,
+) -> Result<Vec<CompNodeId>, SemanticError> {
+    let callee_macro_args = callee
+        .macro_args
+        .iter()
+        .map(|spanned| spanned.inner.clone())
+        .zip(macro_args)
+        .collect();
+    let callee_idents = callee
+        .inputs
+        .iter()
+        .map(|spanned| spanned.inner.clone())
+        .zip(arg_ids)
+        .collect();
+    let outer = ctx.enter_scope((callee_macro_args, callee_idents));
+    ctx.inlining_stack.push(callee.ident.clone());
+
+    // Assignments in the callee aren't visible to the caller.
+    graph_body(
+        ctx,
+        symbols,
+        callee.body.iter().map(|statement| match statement {
+            Statement::Assign(assignment) => assignment,
+            _ => panic!("Inlining function with control flow"),
+        }),
+    )?;
+    let output_ids = callee
+        .outputs
+        .iter()
+        .map(|output| {
+            ctx.get_with_symbols(symbols, &output.inner).ok_or_else(|| {
+                SemanticError::UndeclaredIdentifier("local variable".into(), output.clone())
+            })
+        })
+        .collect();
 
-            let id = ctx.add_node(CompNode::new(produces_value, arg_ids, vec![]), value_source);
+    ctx.inlining_stack.pop();
+    ctx.exit_scope(outer);
 
-            for r in reads.iter() {
-                ctx.record_read(r, id);
-            }
-            for w in writes.iter() {
-                ctx.record_write(w, id);
+    output_ids
+}
+
+/// Graphs straight-line statements, returning the named assignments.
+fn graph_body<'a>(
+    ctx: &mut SemanticContext,
+    symbols: &Symbols,
+    body: impl IntoIterator<Item = &'a Assignment>,
+) -> Result<Vec<(String, CompNodeId)>, SemanticError> {
+    let mut assignments = vec![];
+    for statement in body {
+        // Convert nested expressions to nodes and assign IDs
+        let output_ids = graph_call(ctx, symbols, &statement.expr)?;
+
+        for (spanned_ident, id) in statement.idents.iter().zip(output_ids) {
+            let ident = spanned_ident.inner.clone();
+            if ident == "_" {
+                continue;
             }
 
-            id
+            ctx.set_ident(ident.clone(), id);
+            assignments.push((ident, id));
+        }
+    }
+    Ok(assignments)
+}
+
+/// Graphs the jump ending a block. Leaving the block ends it so every node not already on the stack
+/// upon entry has to be scheduled before the jump, even effects not ordered by `CONTROL_FLOW`
+/// (e.g. `mstore`).
+fn graph_jump(
+    ctx: &mut SemanticContext,
+    symbols: &Symbols,
+    op_ident: &str,
+    operand_ids: Vec<CompNodeId>,
+    input_ids: &[CompNodeId],
+) -> CompNodeId {
+    let Some(Spanned {
+        inner: Symbol::Op(op),
+        ..
+    }) = symbols.get(op_ident)
+    else {
+        panic!("Jump op {} missing from symbols", op_ident)
+    };
+    let id = ctx.add_node(
+        CompNode::new(false, operand_ids, vec![]),
+        ValueSource::Op(op_ident.to_string()),
+    );
+    for r in op.reads.iter() {
+        ctx.record_read(r, id);
+    }
+    for w in op.writes.iter() {
+        ctx.record_write(w, id);
+    }
+
+    let post = &mut ctx.nodes_sources[id].0.post;
+    for other_id in 0..id {
+        if !input_ids.contains(&other_id) && !post.contains(&other_id) {
+            post.push(other_id);
         }
     }
+
+    id
 }
 
-fn set_blocked_count(input_ids: &[CompNodeId], output_ids: &[CompNodeId], nodes: &mut [CompNode]) {
+pub(crate) fn set_blocked_count(
+    input_ids: &[CompNodeId],
+    output_ids: &[CompNodeId],
+    nodes: &mut [CompNode],
+) {
     let total = nodes.len();
 
     let mut blocked_by = vec![0u32; total];
@@ -264,26 +528,57 @@ fn set_blocked_count(input_ids: &[CompNodeId], output_ids: &[CompNodeId], nodes:
     }
 }
 
-// Needs to return
+/// The IR of one basic block of a function.
+#[derive(Clone, Debug)]
+pub struct IRBlock {
+    /// Label marking the block as a jump destination.
+    pub label: Option<String>,
+    /// Variables on the stack when entering the block, top of the stack first.
+    pub inputs: Vec<String>,
+    pub graph: IRGraph,
+    pub sources: Vec<ValueSource>,
+    pub assignments: Vec<(String, CompNodeId)>,
+}
+
+/// Generates the IR graphs of a function, one per basic block (see
+/// [`crate::transformer::control_flow`]). Calls to other BALLS functions are spliced into the
+/// graph if they're marked `inline` (at the call or the definition) or if `inline_by_default` is
+/// set and neither is marked `noinline`. Fails if an output isn't assigned, which the analysis is
+/// expected to have caught.
 pub fn gen_ir(
     func: &Function,
     symbols: &Symbols,
-) -> (IRGraph, Vec<ValueSource>, Vec<(String, CompNodeId)>) {
+    inline_by_default: bool,
+) -> Result<Vec<IRBlock>, SemanticError> {
+    lower_function(func)
+        .into_iter()
+        .map(|block| gen_block_ir(func, block, symbols, inline_by_default))
+        .collect()
+}
+
+fn gen_block_ir(
+    func: &Function,
+    block: BasicBlock,
+    symbols: &Symbols,
+    inline_by_default: bool,
+) -> Result<IRBlock, SemanticError> {
     // Assign IDs to inputs and validate uniqueness.
     let mut ctx = SemanticContext::new(
         func.macro_args
             .iter()
             .map(|spanned| spanned.inner.clone())
             .collect(),
+        inline_by_default,
     );
+    ctx.inlining_stack.push(func.ident.clone());
 
     // Verify that there are no duplicate input identifiers and create nodes. Also reverse IDs so
     // that they are in the right order for the stack (syntax interpreted as left-to-right
     // top-to-bottom).
-    let input_ids: Vec<_> = func
+    let input_ids: Vec<_> = block
         .inputs
         .iter()
-        .map(|Spanned { inner: ident, .. }| {
+        .map(|ident| {
             let id = ctx.add_node(
                 CompNode::lone(true),
                 ValueSource::TopLevelInput(ident.clone()),
@@ -295,31 +590,46 @@ pub fn gen_ir(
         .collect();
 
     // Assign IDs to statements.
-    let assignments: Vec<_> = func
-        .body
-        .iter()
-        .filter_map(|statement| {
-            // Convert nested expressions to nodes and assign IDs
-            let id = graph_expr(&mut ctx, symbols, &statement.expr.inner);
-
-            let spanned_ident = statement.ident.as_ref()?;
-            let ident = spanned_ident.inner.clone();
-
-            ctx.set_ident(ident.clone(), id);
-
-            Some((ident, id))
-        })
-        .collect();
+    let assignments = graph_body(&mut ctx, symbols, &block.assignments)?;
 
     // Validate outputs and retrieve their IDs.
-    let output_ids: Vec<_> = func
+    let output_ids: Vec<_> = block
         .outputs
         .iter()
         .map(|output| {
-            ctx.get_with_symbols(symbols, &output.inner)
-                .unwrap_or_else(|| panic!("TODO: Undefined output identifer {:?}", output))
+            ctx.get_with_symbols(symbols, output).ok_or_else(|| {
+                // Outputs other than the function's are live variables, only ever assigned ones.
+                let output = func
+                    .outputs
+                    .iter()
+                    .find(|spanned| spanned.inner == *output)
+                    .cloned()
+                    .unwrap_or_else(|| Spanned::new(output.clone(), 0..0));
+                SemanticError::UndeclaredIdentifier("local variable".into(), output)
+            })
         })
-        .collect();
+        .collect::<Result<_, _>>()?;
+
+    match block.terminator {
+        Terminator::FallThrough => {}
+        Terminator::Jump(label) => {
+            let label_id = ctx.add_node(CompNode::lone(true), ValueSource::Label(label));
+            graph_jump(&mut ctx, symbols, "jump", vec![label_id], &input_ids);
+        }
+        Terminator::JumpIf(condition, label) => {
+            let first_id = ctx.nodes_sources.len();
+            let condition_id = graph_expr(&mut ctx, symbols, &condition)?;
+            let label_id = ctx.add_node(CompNode::lone(true), ValueSource::Label(label));
+            graph_jump(
+                &mut ctx,
+                symbols,
+                "jumpi",
+                vec![label_id, condition_id],
+                &input_ids,
+            );
+            ctx.span_new_nodes(first_id, &condition.span);
+        }
+    }
 
     let (mut nodes, sources): (Vec<CompNode>, Vec<ValueSource>) =
         ctx.nodes_sources.into_iter().unzip();
@@ -347,8 +657,10 @@ pub fn gen_ir(
         })
         .collect();
 
-    (
-        IRGraph {
+    Ok(IRBlock {
+        label: block.label,
+        inputs: block.inputs,
+        graph: IRGraph {
             input_ids,
             output_ids,
             nodes,
@@ -356,5 +668,116 @@ pub fn gen_ir(
         },
         sources,
         assignments,
-    )
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::scheduling::astar::{AStarScheduler, SearchBudget};
+    use crate::scheduling::cost::SwapCount;
+    use crate::scheduling::schedulers::Dijkstra;
+    use crate::scheduling::step::{validate_steps, Step};
+    use crate::test_utils::{function_blocks, get_symbols};
+
+    const DIVMOD: &str = "extern DIVMOD() stack(2, 2)\n";
+
+    /// Optimal schedule of the block, checked to be valid.
+    fn schedule(block: &IRBlock) -> Vec<Step> {
+        let (steps, _) = Dijkstra
+            .schedule(&block.graph, &SwapCount, 1024, SearchBudget::default())
+            .unwrap();
+        validate_steps(&block.graph, &steps).unwrap();
+        steps
+    }
+
+    fn find_invoke(block: &IRBlock, name: &str) -> CompNodeId {
+        block
+            .sources
+            .iter()
+            .position(
+                |source| matches!(source, ValueSource::MacroInvoke(ident, _) if ident == name),
+            )
+            .unwrap()
+    }
+
+    fn assigned(block: &IRBlock, name: &str) -> Option<CompNodeId> {
+        block
+            .assignments
+            .iter()
+            .find(|(ident, _)| ident == name)
+            .map(|(_, id)| *id)
+    }
+
+    #[test]
+    fn test_destructuring() {
+        let src = format!("{}fn F(x, y) -> (r, q) {{ (q, r) = DIVMOD(x, y) }}", DIVMOD);
+        let block = function_blocks(&src, "F").remove(0);
+        let divmod = find_invoke(&block, "DIVMOD");
+        let projections = block.graph.nodes[divmod].projections.clone();
+        assert_eq!(projections.len(), 2);
+        for (i, id) in projections.iter().enumerate() {
+            assert_eq!(block.graph.nodes[*id].projection_of, Some(divmod));
+            assert!(
+                matches!(block.sources[*id], ValueSource::Projection(of, j) if of == divmod && j == i)
+            );
+        }
+        // The first identifier is assigned the top output.
+        assert_eq!(assigned(&block, "q"), Some(projections[0]));
+        assert_eq!(assigned(&block, "r"), Some(projections[1]));
+        assert_eq!(block.graph.output_ids, vec![projections[1], projections[0]]);
+
+        // Only computable by undoing the multi-output node with all its outputs at once.
+        let steps = schedule(&block);
+        assert_eq!(
+            steps
+                .iter()
+                .filter(|step| matches!(step, Step::Comp(id, _) if *id == divmod))
+                .count(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_discarded_outputs() {
+        let src = format!("{}fn F(x, y) -> (r) {{ (_, r) = DIVMOD(x, y) }}", DIVMOD);
+        let block = function_blocks(&src, "F").remove(0);
+        let divmod = find_invoke(&block, "DIVMOD");
+        let projections = &block.graph.nodes[divmod].projections;
+        assert_eq!(assigned(&block, "_"), None);
+        assert_eq!(assigned(&block, "r"), Some(projections[1]));
+        assert_eq!(block.graph.output_ids, vec![projections[1]]);
+
+        // The discarded quotient is popped right after being computed.
+        let steps = schedule(&block);
+        assert_eq!(steps, vec![Step::Comp(divmod, false), Step::Pop]);
+    }
+
+    #[test]
+    fn test_inlining() {
+        let src = "inline fn G(x) -> (y) { y = add(x, 1) }\nfn F(x) -> (y) { y = G(x) }";
+        let block = function_blocks(src, "F").remove(0);
+        assert!(!block
+            .sources
+            .iter()
+            .any(|source| matches!(source, ValueSource::MacroInvoke(_, _))));
+        assert!(block
+            .sources
+            .iter()
+            .any(|source| matches!(source, ValueSource::Op(op) if op == "add")));
+
+        // Outputs of the callee that were never assigned are an error, not a panic.
+        let mut symbols = get_symbols(src);
+        let Symbol::Function(callee) = &mut symbols.get_mut("G").unwrap().inner else {
+            panic!("G is not a function");
+        };
+        callee.outputs[0].inner = "z".into();
+        let Symbol::Function(func) = &symbols["F"].inner else {
+            panic!("F is not a function");
+        };
+        assert!(matches!(
+            gen_ir(func, &symbols, false),
+            Err(SemanticError::UndeclaredIdentifier(_, ident)) if ident.inner == "z"
+        ));
+    }
 }
@@ -1,3 +1,7 @@
 pub mod analysis;
+pub mod control_flow;
+pub mod cse;
+pub mod huff_import;
 pub mod ir_gen;
+pub mod remat;
 pub mod std_evm;
@@ -0,0 +1,40 @@
+// Marks the values the scheduler may compute again instead of having to duplicate them.
+
+use crate::parser::Spanned;
+use crate::transformer::analysis::{Symbol, Symbols};
+use crate::transformer::ir_gen::{IRBlock, ValueSource};
+use crate::transformer::std_evm::VOLATILE_OPS;
+
+fn is_cheap_constant(symbols: &Symbols, source: &ValueSource) -> bool {
+    match source {
+        ValueSource::MacroArg(_) | ValueSource::HuffConst(_) | ValueSource::Label(_) => true,
+        ValueSource::Op(ident) => matches!(
+            symbols.get(ident),
+            Some(Spanned {
+                inner: Symbol::Op(op),
+                ..
+            }) if op.stack_in == 0 && op.writes.is_empty() && !VOLATILE_OPS.contains(&ident.as_str())
+        ),
+        _ => false,
+    }
+}
+
+/// Marks nullary values that can be recomputed at any point (literals, constants, labels and
+/// opcodes like `caller`) as rematerializable. Reads are only eligible if they aren't ordered
+/// relative to any write in the block, e.g. `returndatasize` without a call.
+pub fn mark_rematerializable(block: &mut IRBlock, symbols: &Symbols) {
+    let nodes = &mut block.graph.nodes;
+    let ordered: Vec<_> = nodes
+        .iter()
+        .flat_map(|node| node.post.iter().copied())
+        .collect();
+
+    for (id, node) in nodes.iter_mut().enumerate() {
+        node.rematerializable = node.produces_value
+            && node.operands.is_empty()
+            && node.post.is_empty()
+            && !ordered.contains(&id)
+            && !block.graph.input_ids.contains(&id)
+            && is_cheap_constant(symbols, &block.sources[id]);
+    }
+}
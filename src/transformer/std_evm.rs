@@ -71,6 +71,138 @@ impl Op {
     }
 }
 
+/// Opcode an op is emitted as. `diff` is a `sub` whose operands may be swapped, for when only
+/// whether the result is zero matters.
+pub fn emitted_op(ident: &str) -> &str {
+    match ident {
+        "diff" => "sub",
+        ident => ident,
+    }
+}
+
+/// Static gas cost of an opcode. Opcodes with dynamic costs are charged their minimum, assuming warm
+/// accesses, no memory expansion and no copied data.
+pub fn static_gas(ident: &str) -> u32 {
+    match emitted_op(ident) {
+        "address" | "origin" | "caller" | "callvalue" | "calldatasize" | "codesize"
+        | "gasprice" | "returndatasize" | "coinbase" | "timestamp" | "number" | "prevrandao"
+        | "gaslimit" | "chainid" | "basefee" | "blobbasefee" | "gas" | "msize" => 2,
+        "add" | "sub" | "lt" | "gt" | "slt" | "sgt" | "eq" | "iszero" | "and" | "or" | "xor"
+        | "not" | "byte" | "shl" | "shr" | "sar" | "calldataload" | "calldatacopy" | "codecopy"
+        | "returndatacopy" | "mload" | "mstore" | "mstore8" | "mcopy" | "blobhash" => 3,
+        "mul" | "div" | "sdiv" | "mod" | "smod" | "signextend" | "selfbalance" => 5,
+        "addmod" | "mulmod" | "jump" => 8,
+        "exp" | "jumpi" => 10,
+        "blockhash" => 20,
+        "sha3" => 30,
+        "balance" | "extcodesize" | "extcodecopy" | "extcodehash" | "sload" | "sstore"
+        | "tload" | "tstore" | "call" | "callcode" | "delegatecall" | "staticcall" => 100,
+        "log0" => 375,
+        "log1" => 750,
+        "log2" => 1125,
+        "log3" => 1500,
+        "log4" => 1875,
+        "selfdestruct" => 5000,
+        "create" | "create2" => 32000,
+        _ => 0,
+    }
+}
+
+/// Byte encoding of an opcode.
+pub fn opcode(ident: &str) -> Option<u8> {
+    let byte = match ident {
+        "stop" => 0x00,
+        "add" => 0x01,
+        "mul" => 0x02,
+        "sub" => 0x03,
+        "div" => 0x04,
+        "sdiv" => 0x05,
+        "mod" => 0x06,
+        "smod" => 0x07,
+        "addmod" => 0x08,
+        "mulmod" => 0x09,
+        "exp" => 0x0a,
+        "signextend" => 0x0b,
+        "lt" => 0x10,
+        "gt" => 0x11,
+        "slt" => 0x12,
+        "sgt" => 0x13,
+        "eq" => 0x14,
+        "iszero" => 0x15,
+        "and" => 0x16,
+        "or" => 0x17,
+        "xor" => 0x18,
+        "not" => 0x19,
+        "byte" => 0x1a,
+        "shl" => 0x1b,
+        "shr" => 0x1c,
+        "sar" => 0x1d,
+        "sha3" => 0x20,
+        "address" => 0x30,
+        "balance" => 0x31,
+        "origin" => 0x32,
+        "caller" => 0x33,
+        "callvalue" => 0x34,
+        "calldataload" => 0x35,
+        "calldatasize" => 0x36,
+        "calldatacopy" => 0x37,
+        "codesize" => 0x38,
+        "codecopy" => 0x39,
+        "gasprice" => 0x3a,
+        "extcodesize" => 0x3b,
+        "extcodecopy" => 0x3c,
+        "returndatasize" => 0x3d,
+        "returndatacopy" => 0x3e,
+        "extcodehash" => 0x3f,
+        "blockhash" => 0x40,
+        "coinbase" => 0x41,
+        "timestamp" => 0x42,
+        "number" => 0x43,
+        "prevrandao" => 0x44,
+        "gaslimit" => 0x45,
+        "chainid" => 0x46,
+        "selfbalance" => 0x47,
+        "basefee" => 0x48,
+        "blobhash" => 0x49,
+        "blobbasefee" => 0x4a,
+        "pop" => 0x50,
+        "mload" => 0x51,
+        "mstore" => 0x52,
+        "mstore8" => 0x53,
+        "sload" => 0x54,
+        "sstore" => 0x55,
+        "jump" => 0x56,
+        "jumpi" => 0x57,
+        "msize" => 0x59,
+        "gas" => 0x5a,
+        "jumpdest" => 0x5b,
+        "tload" => 0x5c,
+        "tstore" => 0x5d,
+        "mcopy" => 0x5e,
+        "log0" => 0xa0,
+        "log1" => 0xa1,
+        "log2" => 0xa2,
+        "log3" => 0xa3,
+        "log4" => 0xa4,
+        "create" => 0xf0,
+        "call" => 0xf1,
+        "callcode" => 0xf2,
+        "return" => 0xf3,
+        "delegatecall" => 0xf4,
+        "create2" => 0xf5,
+        "staticcall" => 0xfa,
+        "revert" => 0xfd,
+        "invalid" => 0xfe,
+        "selfdestruct" => 0xff,
+        _ => return None,
+    };
+    Some(byte)
+}
+
+/// Opcodes without declared dependencies whose result still changes between executions, two uses
+/// are never interchangeable.
+pub const VOLATILE_OPS: [&str; 1] = ["gas"];
+
 pub fn get_standard_opcodes_and_deps() -> (Vec<&'static str>, Vec<Op>) {
     let dependencies = vec![
         "STORAGE",
@@ -107,8 +239,7 @@ pub fn get_standard_opcodes_and_deps() -> (Vec<&'static str>, Vec<Op>) {
         Op::two_comm("and"),
         Op::two_comm("or"),
         Op::two_comm("xor"),
-        // Aliased into sub in the huff formatter,
-        // TODO: Generalize
+        // Emitted as `sub`, see `emitted_op`.
         Op::two_comm("diff"),
         Op::pure("not", 1, true),
         Op::pure("byte", 2, true),